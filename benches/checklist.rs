@@ -0,0 +1,42 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use local_dns_forwarder::CheckList;
+use wildmatch::WildMatch;
+
+fn patterns(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("*.domain{i}.example")).collect()
+}
+
+fn bench_check(c: &mut Criterion) {
+    let mut group = c.benchmark_group("check");
+    for n in [1_000, 10_000, 100_000] {
+        let patterns = patterns(n);
+        let mut list = CheckList::in_memory();
+        for pattern in patterns.iter() {
+            list.add(pattern);
+        }
+        // Equivalent of the former implementation that scans every wildcard pattern
+        let linear = patterns
+            .iter()
+            .map(|x| WildMatch::new(x))
+            .collect::<Vec<_>>();
+
+        let hit = format!("www.domain{}.example", n / 2);
+        let miss = "www.not-listed.example.org";
+        group.bench_with_input(BenchmarkId::new("checklist_hit", n), &hit, |b, name| {
+            b.iter(|| list.check(black_box(name)))
+        });
+        group.bench_with_input(BenchmarkId::new("checklist_miss", n), &miss, |b, name| {
+            b.iter(|| list.check(black_box(name)))
+        });
+        group.bench_with_input(BenchmarkId::new("linear_hit", n), &hit, |b, name| {
+            b.iter(|| linear.iter().any(|x| x.matches(black_box(name))))
+        });
+        group.bench_with_input(BenchmarkId::new("linear_miss", n), &miss, |b, name| {
+            b.iter(|| linear.iter().any(|x| x.matches(black_box(name))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_check);
+criterion_main!(benches);
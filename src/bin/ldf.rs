@@ -1,46 +1,402 @@
 use anyhow::Result;
-use clap::Parser;
-use local_dns_forwarder::logger::{self, LogContext};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
+use local_dns_forwarder::control::{self, ControlAddress, ControlConfig};
+use local_dns_forwarder::control::{
+    Command, CommandError, CountEntry, ErrorCode, HourEntry, ListEntry, Reply, Response,
+    StatsReport,
+};
+use local_dns_forwarder::logger::QUERY_TARGET;
+use local_dns_forwarder::logger::{self, LogContext, LogOutput, LogPolicy, LogRotation};
+use local_dns_forwarder::metrics;
+use local_dns_forwarder::ListReport;
 use local_dns_forwarder::{get_build_mode, get_version, CheckList, CompositeCheckList, Server};
-use local_dns_forwarder::{ResolveEvent, ResolvedData, ResolvedStatus};
+use local_dns_forwarder::{subscription, ListFormat, ListKind, Subscription};
+use local_dns_forwarder::{ClientGroup, DefaultAction, Schedule, ScheduledList};
+use local_dns_forwarder::{History, HistoryFilter, HistoryResolveEvent};
+use local_dns_forwarder::{
+    JsonResolveEvent, LearnedNames, LogSuppressor, Metrics, QueryContext, ResolveEvent,
+    ResolvedData, ResolvedStatus, Stats,
+};
 use serde::Deserialize;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::NonBlocking;
 
 #[derive(Debug, Parser)]
 struct Cli {
     /// Path to config file
-    #[arg(short = 'f', long, value_name = "FILE")]
+    #[arg(short = 'f', long, value_name = "FILE", global = true)]
     config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<SubCommand>,
 }
 
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+    /// Search the query history
+    History(HistoryArgs),
+    /// Send a command to the running instance through the control server
+    Ctl(CtlArgs),
+    /// Check the config and the lists without starting the server
+    CheckConfig,
+}
+
+#[derive(Debug, Args)]
+struct HistoryArgs {
+    /// Queries at or after the time ("YYYY-MM-DD HH:MM[:SS]" or "YYYY-MM-DD" in local time)
+    #[arg(long, value_name = "TIME")]
+    from: Option<String>,
+    /// Queries before the time
+    #[arg(long, value_name = "TIME")]
+    to: Option<String>,
+    /// Queries from the client address
+    #[arg(long, value_name = "ADDRESS")]
+    client: Option<String>,
+    /// Queries whose name contains the text
+    #[arg(long, value_name = "TEXT")]
+    name: Option<String>,
+    /// Queries with the decision (allow, deny, nocheck, unlisted or learned)
+    #[arg(long)]
+    decision: Option<String>,
+    /// Maximum number of queries to show, counted from the newest
+    #[arg(long, default_value_t = 100)]
+    limit: usize,
+}
+
+#[derive(Debug, Args)]
+struct CtlArgs {
+    /// Address of the control server ("<address>:<port>" or "unix:<path>", default: [control] address).
+    /// The config is not read unless -f is given.
+    #[arg(long, value_name = "ADDRESS")]
+    address: Option<String>,
+    /// Token of the control server (default: $LDF_CONTROL_TOKEN, or [control] token)
+    #[arg(long, value_name = "TOKEN", conflicts_with = "token_file")]
+    token: Option<String>,
+    /// File containing the token of the control server
+    #[arg(long, value_name = "FILE")]
+    token_file: Option<PathBuf>,
+    /// Print the response as JSON
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum CtlCommand {
+    /// Add the FQDN to the allowlist
+    Allow {
+        fqdn: String,
+        /// Remove the entry after the duration (e.g. "30m")
+        #[arg(long = "for", value_name = "DURATION", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Add the FQDN to the denylist
+    Deny {
+        fqdn: String,
+        /// Remove the entry after the duration (e.g. "30m")
+        #[arg(long = "for", value_name = "DURATION", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Remove the entry from the list
+    Remove { list: CtlList, fqdn: String },
+    /// List the entries
+    List {
+        #[arg(default_value = "allow")]
+        list: CtlList,
+    },
+    /// Write the list to its file (default: both)
+    Save { list: Option<CtlList> },
+    /// Show which list and entry match the FQDN
+    Check { fqdn: String },
+    /// Read the list files again
+    Reload,
+    /// Change the level of the diagnostic log
+    Loglevel { level: String },
+    /// List the FQDNs recorded in learning mode
+    Learned,
+    /// Add the learned FQDNs to the allowlist
+    Promote {
+        #[arg(required_unless_present = "all")]
+        fqdns: Vec<String>,
+        #[arg(long, conflicts_with = "fqdns")]
+        all: bool,
+    },
+    /// Discard the learned FQDNs
+    Forget {
+        #[arg(required_unless_present = "all")]
+        fqdns: Vec<String>,
+        #[arg(long, conflicts_with = "fqdns")]
+        all: bool,
+    },
+    /// Show the statistics, or clear them with "reset"
+    Stats {
+        #[arg(value_enum, default_value = "top-queried")]
+        report: CtlStats,
+        /// Number of entries to show (default: 10, or 24 for hourly)
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CtlList {
+    Allow,
+    Deny,
+}
+
+impl From<CtlList> for ListKind {
+    fn from(v: CtlList) -> Self {
+        match v {
+            CtlList::Allow => ListKind::Allow,
+            CtlList::Deny => ListKind::Deny,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CtlStats {
+    TopQueried,
+    TopBlocked,
+    TopClients,
+    Hourly,
+    Reset,
+}
+
+impl From<CtlCommand> for Command {
+    fn from(v: CtlCommand) -> Self {
+        match v {
+            CtlCommand::Allow { fqdn, duration } => Command::Allow {
+                name: fqdn,
+                duration,
+            },
+            CtlCommand::Deny { fqdn, duration } => Command::Deny {
+                name: fqdn,
+                duration,
+            },
+            CtlCommand::Remove { list, fqdn } => Command::Remove {
+                list: list.into(),
+                name: fqdn,
+            },
+            CtlCommand::List { list } => Command::List { list: list.into() },
+            CtlCommand::Save { list } => Command::Save {
+                list: list.map(Into::into),
+            },
+            CtlCommand::Check { fqdn } => Command::Check { name: fqdn },
+            CtlCommand::Reload => Command::Reload,
+            CtlCommand::Loglevel { level } => Command::Log { level },
+            CtlCommand::Learned => Command::Learned,
+            CtlCommand::Promote { fqdns, all } => Command::Promote { names: fqdns, all },
+            CtlCommand::Forget { fqdns, all } => Command::Forget { names: fqdns, all },
+            CtlCommand::Stats { report, limit } => {
+                let report = match report {
+                    CtlStats::TopQueried => StatsReport::TopQueried,
+                    CtlStats::TopBlocked => StatsReport::TopBlocked,
+                    CtlStats::TopClients => StatsReport::TopClients,
+                    CtlStats::Hourly => StatsReport::Hourly,
+                    CtlStats::Reset => return Command::StatsReset,
+                };
+                Command::Stats { report, limit }
+            }
+        }
+    }
+}
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/ldf/config.toml";
+
+/// Maximum number of issues shown for each list
+const MAX_LIST_ISSUES: usize = 20;
+
+/// Exit code when the command failed
+const EXIT_FAILED: i32 = 1;
+/// Exit code when the control server could not be reached
+const EXIT_UNAVAILABLE: i32 = 3;
+
+/// Environment variable that gives the token to `ldf ctl`
+const CONTROL_TOKEN_ENV: &str = "LDF_CONTROL_TOKEN";
+
 #[derive(Debug, Deserialize)]
 struct GeneralConfig {
     loglevel: Option<String>,
+    query_loglevel: Option<String>,
     log_dir: Option<PathBuf>,
     output_allowed_log: Option<bool>,
     output_nochecked_log: Option<bool>,
     allowlist: Option<PathBuf>,
     denylist: Option<PathBuf>,
+    blocklists: Option<Vec<BlockListConfig>>,
+    cache_dir: Option<PathBuf>,
+    json_log: Option<JsonLogOutput>,
+    log_threshold: Option<usize>,
+    log_window: Option<String>,
+    log_rotation: Option<LogRotation>,
+    log_max_age: Option<String>,
+    log_max_size: Option<String>,
+    log_max_files: Option<usize>,
+    log_compress: Option<bool>,
+    log_output: Option<LogOutputKind>,
+    syslog: Option<String>,
+}
+
+/// Destination of the logs other than the log files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogOutputKind {
+    Stdout,
+    Journald,
+    Syslog,
+}
+
+/// Destination of the JSON query log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JsonLogOutput {
+    Stdout,
+    File,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockListConfig {
+    path: PathBuf,
+    format: Option<ListFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionConfig {
+    name: String,
+    url: String,
+    kind: Option<ListKind>,
+    format: Option<ListFormat>,
+    interval: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientGroupConfig {
+    name: String,
+    networks: Vec<String>,
+    default: Option<DefaultAction>,
+    allowlist: Option<PathBuf>,
+    denylist: Option<PathBuf>,
+    blocklists: Option<Vec<BlockListConfig>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScheduledListConfig {
+    name: String,
+    kind: Option<ListKind>,
+    path: PathBuf,
+    schedules: Vec<ScheduleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScheduleConfig {
+    days: String,
+    hours: String,
 }
 
 impl Default for GeneralConfig {
     fn default() -> Self {
         Self {
             loglevel: Some("info".into()),
+            query_loglevel: Some("info".into()),
             log_dir: None,
             output_allowed_log: Some(false),
             output_nochecked_log: Some(false),
             allowlist: None,
             denylist: None,
+            blocklists: None,
+            cache_dir: None,
+            json_log: None,
+            log_threshold: Some(3),
+            log_window: Some("1h".into()),
+            log_rotation: None,
+            log_max_age: None,
+            log_max_size: None,
+            log_max_files: None,
+            log_compress: None,
+            log_output: None,
+            syslog: None,
         }
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct MetricsConfig {
+    address: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ControlSectionConfig {
+    address: Option<String>,
+    mode: Option<String>,
+    token: Option<String>,
+    token_file: Option<PathBuf>,
+}
+
+impl ControlSectionConfig {
+    fn address(&self) -> Result<ControlAddress> {
+        match self.address.as_deref() {
+            Some(v) => Ok(v.parse::<ControlAddress>()?),
+            None => Ok(ControlAddress::default()),
+        }
+    }
+
+    /// Returns the token, reading `token_file` if it is specified
+    fn token(&self) -> Result<Option<String>> {
+        let token = match (self.token.as_ref(), self.token_file.as_ref()) {
+            (Some(_), Some(_)) => anyhow::bail!("Specify either token or token_file"),
+            (Some(token), None) => Some(token.clone()),
+            (None, Some(path)) => Some(read_token_file(path)?),
+            (None, None) => None,
+        };
+        if token.as_deref() == Some("") {
+            anyhow::bail!("The control token is empty");
+        }
+        Ok(token)
+    }
+}
+
+fn read_token_file(path: &Path) -> Result<String> {
+    Ok(std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {} ({e})", path.display()))?
+        .trim()
+        .to_string())
+}
+
+/// Part of the config used by `ldf ctl`, which ignores the other sections
+#[derive(Debug, Deserialize)]
+struct CtlConfig {
+    control: Option<ControlSectionConfig>,
+}
+
+impl CtlConfig {
+    fn load(path: impl AsRef<Path>) -> Result<ControlSectionConfig> {
+        let text = std::fs::read_to_string(path)?;
+        let config = toml::from_str::<CtlConfig>(&text)?;
+        Ok(config.control.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryConfig {
+    path: PathBuf,
+    retention: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct Config {
     general: Option<GeneralConfig>,
     server: local_dns_forwarder::Config,
+    metrics: Option<MetricsConfig>,
+    history: Option<HistoryConfig>,
+    control: Option<ControlSectionConfig>,
+    subscriptions: Option<Vec<SubscriptionConfig>>,
+    client_groups: Option<Vec<ClientGroupConfig>>,
+    scheduled_lists: Option<Vec<ScheduledListConfig>>,
 }
 
 impl Config {
@@ -60,17 +416,54 @@ impl Default for Config {
         Self {
             general: Some(GeneralConfig::default()),
             server: local_dns_forwarder::Config::default(),
+            metrics: None,
+            history: None,
+            control: None,
+            subscriptions: None,
+            client_groups: None,
+            scheduled_lists: None,
         }
     }
 }
 
+struct InnerClientGroupConfig {
+    name: String,
+    networks: Vec<IpNet>,
+    default_action: DefaultAction,
+    allowlist: Option<PathBuf>,
+    denylist: Option<PathBuf>,
+    blocklists: Vec<(PathBuf, ListFormat)>,
+}
+
+struct InnerScheduledListConfig {
+    name: String,
+    kind: ListKind,
+    path: PathBuf,
+    schedules: Vec<Schedule>,
+}
+
 struct InnerConfig {
     loglevel: tracing::Level,
+    query_loglevel: LevelFilter,
     log_dir: Option<PathBuf>,
+    log_policy: LogPolicy,
+    log_output: LogOutput,
     output_allowed_log: bool,
     output_nochecked_log: bool,
     allowlist: Option<PathBuf>,
     denylist: Option<PathBuf>,
+    blocklists: Vec<(PathBuf, ListFormat)>,
+    cache_dir: PathBuf,
+    json_log: Option<JsonLogOutput>,
+    log_threshold: usize,
+    log_window: Duration,
+    subscriptions: Vec<Subscription>,
+    client_groups: Vec<InnerClientGroupConfig>,
+    scheduled_lists: Vec<InnerScheduledListConfig>,
+    metrics_address: Option<SocketAddr>,
+    /// Path to the query history and how long the queries are kept
+    history: Option<(PathBuf, Duration)>,
+    control: ControlConfig,
     server: local_dns_forwarder::Config,
 }
 
@@ -83,11 +476,43 @@ impl InnerConfig {
         } else {
             tracing::Level::INFO
         };
+        let query_loglevel = if let Some(level) = general.query_loglevel.as_ref() {
+            LevelFilter::from_str(level)?
+        } else {
+            LevelFilter::INFO
+        };
         let log_dir = if let Some(log_dir) = general.log_dir {
             Some(absolute_path(log_dir)?)
         } else {
             None
         };
+        let log_policy = LogPolicy {
+            rotation: general.log_rotation.unwrap_or_default(),
+            max_age: match general.log_max_age.as_deref() {
+                Some("none") => None,
+                Some(v) => Some(humantime::parse_duration(v)?),
+                None => LogPolicy::default().max_age,
+            },
+            max_size: general
+                .log_max_size
+                .as_deref()
+                .map(logger::parse_size)
+                .transpose()?,
+            max_files: general.log_max_files,
+            compress: general.log_compress.unwrap_or(false),
+        };
+        let log_output = match general.log_output.unwrap_or(LogOutputKind::Stdout) {
+            LogOutputKind::Stdout => LogOutput::Stdout,
+            LogOutputKind::Journald => LogOutput::Journald,
+            LogOutputKind::Syslog => LogOutput::Syslog(
+                general
+                    .syslog
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?
+                    .unwrap_or_default(),
+            ),
+        };
         let allowlist = if let Some(allowlist) = general.allowlist {
             Some(absolute_path(allowlist)?)
         } else {
@@ -98,60 +523,273 @@ impl InnerConfig {
         } else {
             None
         };
+        let blocklists = blocklist_paths(general.blocklists)?;
+        let cache_dir = if let Some(cache_dir) = general.cache_dir {
+            absolute_path(cache_dir)?
+        } else {
+            Path::new("/var/cache/ldf").to_path_buf()
+        };
+        if general.json_log == Some(JsonLogOutput::File) && log_dir.is_none() {
+            anyhow::bail!("log_dir is required to write the JSON query log to a file");
+        }
+        let log_window = if let Some(log_window) = general.log_window.as_ref() {
+            humantime::parse_duration(log_window)?
+        } else {
+            Duration::from_secs(60 * 60)
+        };
+        if log_window.is_zero() {
+            anyhow::bail!("log_window must be longer than 0");
+        }
+        let mut subscriptions: Vec<Subscription> = Vec::new();
+        for v in config.subscriptions.unwrap_or_default() {
+            if subscriptions.iter().any(|x| x.name == v.name) {
+                anyhow::bail!("Duplicate subscription name: {}", v.name);
+            }
+            let interval = if let Some(interval) = v.interval.as_ref() {
+                humantime::parse_duration(interval)?
+            } else {
+                Duration::from_secs(24 * 60 * 60)
+            };
+            if interval.is_zero() {
+                anyhow::bail!("interval of subscription {} must be longer than 0", v.name);
+            }
+            subscriptions.push(Subscription::new(
+                v.name,
+                v.url,
+                v.kind.unwrap_or_default(),
+                v.format.unwrap_or_default(),
+                interval,
+                &cache_dir,
+            )?);
+        }
+        let mut client_groups: Vec<InnerClientGroupConfig> = Vec::new();
+        for v in config.client_groups.unwrap_or_default() {
+            if client_groups.iter().any(|x| x.name == v.name) {
+                anyhow::bail!("Duplicate client group name: {}", v.name);
+            }
+            let mut networks = Vec::with_capacity(v.networks.len());
+            for network in v.networks.iter() {
+                // A single address is treated as a /32 (or /128) network
+                let network = match network.parse::<IpNet>() {
+                    Ok(network) => network,
+                    Err(e) => network
+                        .parse::<std::net::IpAddr>()
+                        .map(IpNet::from)
+                        .map_err(|_| anyhow::anyhow!("Invalid network {network} ({e})"))?,
+                };
+                networks.push(network);
+            }
+            client_groups.push(InnerClientGroupConfig {
+                name: v.name,
+                networks,
+                default_action: v.default.unwrap_or_default(),
+                allowlist: v.allowlist.map(absolute_path).transpose()?,
+                denylist: v.denylist.map(absolute_path).transpose()?,
+                blocklists: blocklist_paths(v.blocklists)?,
+            });
+        }
+        let mut scheduled_lists = Vec::new();
+        for v in config.scheduled_lists.unwrap_or_default() {
+            let mut schedules = Vec::with_capacity(v.schedules.len());
+            for schedule in v.schedules.iter() {
+                schedules.push(Schedule::parse(&schedule.days, &schedule.hours)?);
+            }
+            scheduled_lists.push(InnerScheduledListConfig {
+                name: v.name,
+                kind: v.kind.unwrap_or_default(),
+                path: absolute_path(v.path)?,
+                schedules,
+            });
+        }
+        let metrics_address = if let Some(metrics) = config.metrics {
+            let address = metrics.address.as_deref().unwrap_or("127.0.0.1:9153");
+            let address = address
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid metrics address {address} ({e})"))?;
+            Some(address)
+        } else {
+            None
+        };
+        let history = if let Some(history) = config.history {
+            let retention = if let Some(retention) = history.retention.as_ref() {
+                humantime::parse_duration(retention)?
+            } else {
+                Duration::from_secs(30 * 24 * 60 * 60)
+            };
+            Some((absolute_path(history.path)?, retention))
+        } else {
+            None
+        };
+        let control = if let Some(control) = config.control {
+            let mode = match control.mode.as_deref() {
+                Some(v) => u32::from_str_radix(v, 8)
+                    .map_err(|e| anyhow::anyhow!("Invalid control socket mode {v} ({e})"))?,
+                None => ControlConfig::default().mode,
+            };
+            ControlConfig {
+                address: control.address()?,
+                token: control.token()?,
+                mode,
+            }
+        } else {
+            ControlConfig::default()
+        };
         Ok(Self {
             loglevel,
+            query_loglevel,
             log_dir,
+            log_policy,
+            log_output,
             output_allowed_log: general.output_allowed_log.unwrap_or(false),
             output_nochecked_log: general.output_nochecked_log.unwrap_or(false),
             allowlist,
             denylist,
+            blocklists,
+            cache_dir,
+            json_log: general.json_log,
+            log_threshold: general.log_threshold.unwrap_or(3),
+            log_window,
+            subscriptions,
+            client_groups,
+            scheduled_lists,
+            metrics_address,
+            history,
+            control,
             server: config.server,
         })
     }
 }
 
+fn blocklist_paths(blocklists: Option<Vec<BlockListConfig>>) -> Result<Vec<(PathBuf, ListFormat)>> {
+    let mut ret = Vec::new();
+    for blocklist in blocklists.unwrap_or_default() {
+        let format = blocklist.format.unwrap_or_default();
+        ret.push((absolute_path(blocklist.path)?, format));
+    }
+    Ok(ret)
+}
+
+/// Logs the query with the fields that journald and syslog record as structured data
+fn log_query(ctx: &QueryContext, status: &ResolvedStatus) {
+    tracing::info!(
+        target: QUERY_TARGET,
+        qname = ctx.qname,
+        qtype = %ctx.qtype,
+        decision = status.decision(),
+        client = %ctx.client.ip(),
+        "{status}"
+    );
+}
+
 pub struct LDFResolveEvent {
-    threshold: usize,
-    count_map: Arc<RwLock<std::collections::HashMap<u64, usize>>>,
+    suppressor: Arc<RwLock<LogSuppressor>>,
     output_allowed_log: bool,
     output_nochecked_log: bool,
+    json: Option<JsonResolveEvent<NonBlocking>>,
+    metrics: Option<Arc<Metrics>>,
+    stats: Option<Arc<RwLock<Stats>>>,
+    history: Option<HistoryResolveEvent>,
 }
 
 impl LDFResolveEvent {
-    fn new(threshold: usize, output_allowed_log: bool, output_nochecked_log: bool) -> Self {
+    fn new(
+        suppressor: Arc<RwLock<LogSuppressor>>,
+        output_allowed_log: bool,
+        output_nochecked_log: bool,
+    ) -> Self {
         Self {
-            threshold,
-            count_map: Default::default(),
+            suppressor,
             output_allowed_log,
             output_nochecked_log,
+            json: None,
+            metrics: None,
+            stats: None,
+            history: None,
+        }
+    }
+
+    fn history(self, history: HistoryResolveEvent) -> Self {
+        Self {
+            history: Some(history),
+            ..self
+        }
+    }
+
+    fn stats(self, stats: Arc<RwLock<Stats>>) -> Self {
+        Self {
+            stats: Some(stats),
+            ..self
+        }
+    }
+
+    fn metrics(self, metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Also writes every query to the JSON query log, regardless of the other settings
+    fn json(self, json: JsonResolveEvent<NonBlocking>) -> Self {
+        Self {
+            json: Some(json),
+            ..self
         }
     }
 
-    fn code(d: &ResolvedData) -> u64 {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        let mut hasher = DefaultHasher::new();
-        d.req_qtype.hash(&mut hasher);
-        d.req_name.hash(&mut hasher);
-        hasher.finish()
+    fn code(d: &ResolvedData) -> String {
+        format!("<{}> {}", d.req_qtype, d.req_name)
     }
 }
 
 impl ResolveEvent for LDFResolveEvent {
-    fn resolving(&self, _name: &str) {}
+    fn resolving(&self, ctx: &QueryContext) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.resolving(ctx);
+        }
+    }
+
+    fn aborted(&self, ctx: &QueryContext) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.aborted(ctx);
+        }
+    }
+
+    fn resolved(&self, ctx: &QueryContext, status: ResolvedStatus) {
+        if let Some(json) = self.json.as_ref() {
+            json.write(ctx, &status);
+        }
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.resolved_ref(ctx, &status);
+        }
+        if let Some(Ok(mut stats)) = self.stats.as_ref().map(|x| x.write()) {
+            stats.record(ctx, &status);
+        }
+        if let Some(history) = self.history.as_ref() {
+            history.write(ctx, &status);
+        }
 
-    fn resolved(&self, status: ResolvedStatus) {
         let mut ignore = false;
         let code = match &status {
-            ResolvedStatus::Allow(v) => {
+            ResolvedStatus::Allow(v, _) => {
+                ignore = !self.output_allowed_log;
+                Self::code(v)
+            }
+            ResolvedStatus::AllowButError(v, ..) => {
+                ignore = !self.output_allowed_log;
+                Self::code(v)
+            }
+            ResolvedStatus::Unlisted(v) => {
                 ignore = !self.output_allowed_log;
                 Self::code(v)
             }
-            ResolvedStatus::AllowButError(v, _) => {
+            ResolvedStatus::UnlistedButError(v, _) => {
                 ignore = !self.output_allowed_log;
                 Self::code(v)
             }
-            ResolvedStatus::Deny(v, _) => Self::code(v),
+            ResolvedStatus::Deny(v, ..) => Self::code(v),
+            ResolvedStatus::Learned(v) => Self::code(v),
+            ResolvedStatus::LearnedButError(v, _) => Self::code(v),
             ResolvedStatus::NoCheck(v) => {
                 ignore = !self.output_nochecked_log;
                 Self::code(v)
@@ -166,17 +804,12 @@ impl ResolveEvent for LDFResolveEvent {
             return;
         }
 
-        if let Ok(mut count_map) = self.count_map.write() {
-            let count = count_map.entry(code).or_insert(0);
-            if *count < self.threshold {
-                tracing::info!("{status}");
-            }
-            if *count + 1 == self.threshold {
-                tracing::warn!("Since the number of requests has exceeded the threshold, log output will be suppressed from now on")
+        if let Ok(mut suppressor) = self.suppressor.write() {
+            if suppressor.check(&code) {
+                log_query(ctx, &status);
             }
-            *count = count.saturating_add(1);
         } else {
-            tracing::info!("{status}");
+            log_query(ctx, &status);
         }
     }
 
@@ -189,7 +822,7 @@ fn get_config_path(cli: &Cli) -> Result<PathBuf> {
     if let Some(config_path) = cli.config.as_ref() {
         absolute_path(config_path)
     } else {
-        Ok(Path::new("/etc/ldf/config.toml").to_path_buf())
+        Ok(Path::new(DEFAULT_CONFIG_PATH).to_path_buf())
     }
 }
 
@@ -218,7 +851,111 @@ fn get_checklist(config: &InnerConfig) -> Result<CompositeCheckList> {
     tracing::info!("[Config] Allowing {} FQDN(s)", allowlist.count());
     tracing::info!("[Config] Denying {} FQDN(s)", denylist.count());
 
-    Ok(CompositeCheckList::new(allowlist, denylist))
+    let mut checklist = CompositeCheckList::new(allowlist, denylist);
+    for (path, format) in config.blocklists.iter() {
+        let blocklist = CheckList::import(path.to_path_buf(), *format)?;
+        tracing::info!(
+            "[Config] BlockList: {} ({}, {} FQDN(s))",
+            path.display(),
+            blocklist.format().unwrap_or(*format),
+            blocklist.count()
+        );
+        checklist.blocklists.push(blocklist);
+    }
+
+    for v in config.scheduled_lists.iter() {
+        let list = CheckList::text(v.path.to_path_buf())?;
+        let schedules = v
+            .schedules
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        tracing::info!(
+            "[Config] ScheduledList: {} ({}, {}, {}, {} FQDN(s))",
+            v.name,
+            v.path.display(),
+            v.kind,
+            schedules.join(", "),
+            list.count()
+        );
+        checklist.scheduled.push(ScheduledList::new(
+            &v.name,
+            v.kind,
+            v.schedules.clone(),
+            list,
+        ));
+    }
+
+    // Use the cached copies until the subscriptions are refreshed
+    for subscription in config.subscriptions.iter() {
+        if !subscription.cache_path().exists() {
+            tracing::info!(
+                "[Config] Subscription: {} ({}, not cached yet)",
+                subscription.name,
+                subscription.url
+            );
+            continue;
+        }
+        let list = subscription.load()?;
+        tracing::info!(
+            "[Config] Subscription: {} ({}, {} FQDN(s))",
+            subscription.name,
+            subscription.url,
+            list.count()
+        );
+        checklist.set_remote(&subscription.name, subscription.kind, list);
+    }
+
+    Ok(checklist)
+}
+
+fn get_client_groups(config: &InnerConfig) -> Result<Vec<ClientGroup>> {
+    let mut ret = Vec::with_capacity(config.client_groups.len());
+    for group in config.client_groups.iter() {
+        let allowlist = if let Some(path) = group.allowlist.as_ref() {
+            CheckList::text(path.to_path_buf())?
+        } else {
+            CheckList::in_memory()
+        };
+        let denylist = if let Some(path) = group.denylist.as_ref() {
+            CheckList::text(path.to_path_buf())?
+        } else {
+            CheckList::in_memory()
+        };
+        let mut checklist = CompositeCheckList::new(allowlist, denylist);
+        for (path, format) in group.blocklists.iter() {
+            checklist
+                .blocklists
+                .push(CheckList::import(path.to_path_buf(), *format)?);
+        }
+
+        let networks = group
+            .networks
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        tracing::info!(
+            "[Config] ClientGroup: {} ({}, default: {}, allowing {} FQDN(s), denying {} FQDN(s))",
+            group.name,
+            networks.join(", "),
+            group.default_action,
+            checklist.allowlist.count(),
+            checklist.denylist.count()
+                + checklist
+                    .blocklists
+                    .iter()
+                    .map(|x| x.count())
+                    .sum::<usize>()
+        );
+        ret.push(ClientGroup::new(
+            &group.name,
+            group.networks.clone(),
+            group.default_action,
+            checklist,
+        ));
+    }
+
+    Ok(ret)
 }
 
 fn absolute_path(path: impl AsRef<Path>) -> Result<PathBuf> {
@@ -232,136 +969,420 @@ fn absolute_path(path: impl AsRef<Path>) -> Result<PathBuf> {
     Ok(ret)
 }
 
-fn on_ipctl(
-    command: &str,
-    reload_handle: &logger::ReloadHandle,
-    checklist: Arc<RwLock<CompositeCheckList>>,
-) -> String {
-    use std::str::FromStr;
-    let inv = || {
-        let msg = format!("Invalid command: {command}");
+/// Returns the name as it is stored in a list, or an error if it cannot be a list entry
+fn valid_entry(name: &str) -> Result<String, CommandError> {
+    CheckList::normalize(name).ok_or_else(|| {
+        let msg = format!("Invalid entry: {name}");
         tracing::error!("{msg}");
-        msg
-    };
+        CommandError::new(ErrorCode::InvalidArgument, msg)
+    })
+}
 
-    let splitted = command.split(' ').collect::<Vec<_>>();
-    if splitted.is_empty() {
-        return inv();
+/// Runs the command from the control server and writes an audit log if it changes the state
+fn on_control(
+    caller: &control::Caller,
+    command: &Command,
+    reload_handle: &logger::ReloadHandle,
+    checklist: Arc<RwLock<CompositeCheckList>>,
+    client_groups: Arc<RwLock<Vec<ClientGroup>>>,
+    learned: Arc<RwLock<LearnedNames>>,
+    stats: Arc<RwLock<Stats>>,
+) -> Result<Reply, CommandError> {
+    let ret = on_command(
+        command,
+        reload_handle,
+        checklist,
+        client_groups,
+        learned,
+        stats,
+    );
+    if command.is_mutating() {
+        let result = match &ret {
+            Ok(v) => v.to_string(),
+            Err(e) => e.message.clone(),
+        };
+        tracing::info!(
+            target: logger::AUDIT_TARGET,
+            "[Audit] {caller}: {command} => {}",
+            result.trim_end().replace('\n', "; ")
+        );
     }
+    ret
+}
 
-    match splitted[0].to_lowercase().as_ref() {
-        "log" => {
-            if splitted.len() < 2 {
-                return inv();
-            }
+fn on_command(
+    command: &Command,
+    reload_handle: &logger::ReloadHandle,
+    checklist: Arc<RwLock<CompositeCheckList>>,
+    client_groups: Arc<RwLock<Vec<ClientGroup>>>,
+    learned: Arc<RwLock<LearnedNames>>,
+    stats: Arc<RwLock<Stats>>,
+) -> Result<Reply, CommandError> {
+    use std::str::FromStr;
+    let fail = |code: ErrorCode, msg: String, cause: &dyn std::fmt::Display| {
+        tracing::error!("{msg}: {cause}");
+        CommandError::new(code, msg)
+    };
 
-            if let Ok(level) = tracing::Level::from_str(splitted[1]) {
-                match reload_handle.modify(|y| *y = level.into()) {
-                    Ok(_) => {
-                        let msg = format!("Log level is changed to {level}");
+    match command {
+        Command::Log { level } => {
+            let Ok(level) = tracing::Level::from_str(level) else {
+                let msg = format!("Failed to convert {level} to log level");
+                tracing::error!("{msg}");
+                return Err(CommandError::new(ErrorCode::InvalidArgument, msg));
+            };
+            reload_handle.modify(|y| *y = level.into()).map_err(|e| {
+                let msg = format!("Failed to change log level to {level}");
+                fail(ErrorCode::Failed, msg, &e)
+            })?;
+            let reply = Reply::LogLevel {
+                level: level.to_string(),
+            };
+            tracing::info!("{reply}");
+            Ok(reply)
+        }
+        Command::Allow { name, duration } | Command::Deny { name, duration } => {
+            let kind = if matches!(command, Command::Allow { .. }) {
+                ListKind::Allow
+            } else {
+                ListKind::Deny
+            };
+            let name = valid_entry(name)?;
+            let mut checklist = checklist.write().map_err(|_| {
+                let msg = format!("Failed to add {name} to {}", kind.label());
+                fail(ErrorCode::Internal, msg, &"Could not get write lock")
+            })?;
+            let list = list_mut(&mut checklist, kind);
+            let added = match duration {
+                Some(duration) => list.add_temporary(&name, *duration) > 0,
+                None => list.add(&name) > 0,
+            };
+            let reply = Reply::Added {
+                name,
+                list: kind,
+                added,
+                duration_secs: duration.map(|x| x.as_secs()),
+            };
+            tracing::info!("{reply}");
+            Ok(reply)
+        }
+        Command::Remove { list: kind, name } => {
+            let name = &valid_entry(name)?;
+            let mut checklist = checklist.write().map_err(|_| {
+                let msg = format!("Failed to remove {name} from {}", kind.label());
+                fail(ErrorCode::Internal, msg, &"Could not get write lock")
+            })?;
+            let removed = list_mut(&mut checklist, *kind).delete(name) > 0;
+            let reply = Reply::Removed {
+                name: name.clone(),
+                list: *kind,
+                removed,
+            };
+            tracing::info!("{reply}");
+            Ok(reply)
+        }
+        Command::Save { list } => {
+            let checklist = checklist.read().map_err(|_| {
+                let msg = "Failed to save lists".to_string();
+                fail(ErrorCode::Internal, msg, &"Could not get read lock")
+            })?;
+            let kinds = match list {
+                Some(kind) => vec![*kind],
+                None => {
+                    // In-memory lists are skipped unless there is no list to save,
+                    // in which case saving the allowlist reports the error as before
+                    let kinds = [ListKind::Allow, ListKind::Deny]
+                        .into_iter()
+                        .filter(|x| list_ref(&checklist, *x).path().is_some())
+                        .collect::<Vec<_>>();
+                    if kinds.is_empty() {
+                        vec![ListKind::Allow]
+                    } else {
+                        kinds
+                    }
+                }
+            };
+            // Each list is saved even if another one fails, and the result is reported per list
+            let mut failed = false;
+            let mut lines = Vec::new();
+            for kind in kinds.iter() {
+                match list_ref(&checklist, *kind).save() {
+                    Ok(()) => {
+                        let msg = format!("{} is saved", kind.label());
                         tracing::info!("{msg}");
-                        msg
+                        lines.push(msg);
                     }
                     Err(e) => {
-                        let msg = format!("Failed to change log lebel to {level}");
-                        tracing::error!("{msg} ({e})");
-                        msg
+                        let msg = format!("Failed to save {}: {e}", kind.label());
+                        tracing::error!("{msg}");
+                        lines.push(msg);
+                        failed = true;
                     }
                 }
+            }
+            if failed {
+                Err(CommandError::new(ErrorCode::Failed, lines.join("\n")))
             } else {
-                let msg = format!("Failed to convert {} to log level", splitted[1]);
-                tracing::error!("{msg}");
-                msg
+                Ok(Reply::Saved { lists: kinds })
             }
         }
-        "allow" => {
-            if splitted.len() < 2 {
-                return inv();
-            }
+        Command::List { list: kind } => {
+            let checklist = checklist.read().map_err(|_| {
+                let msg = format!("Failed to get {}", kind.label());
+                fail(ErrorCode::Internal, msg, &"Could not get read lock")
+            })?;
+            let list = list_ref(&checklist, *kind);
+            let entries = list
+                .iter()
+                .map(|name| ListEntry {
+                    name: name.to_string(),
+                    // Round down to seconds to keep the output short
+                    expires_in_secs: list.expires_in(name).map(|x| x.as_secs()),
+                })
+                .collect();
 
-            let fqdn = splitted[1];
-            let msg = if let Ok(mut checklist) = checklist.write() {
-                let msg = if checklist.allowlist.add(fqdn) > 0 {
-                    format!("Add {fqdn} to AllowList")
-                } else {
-                    format!("{fqdn} is already in AllowList")
-                };
-
-                tracing::info!("{msg}");
-                msg
-            } else {
-                let msg = format!("Failed to add {fqdn} to AllowList");
-                tracing::error!("{msg}");
-                msg
+            tracing::info!("Returned the list of FQDN(s) in {}", kind.label());
+            Ok(Reply::Entries {
+                list: *kind,
+                entries,
+            })
+        }
+        Command::Check { name } => {
+            let checklist = checklist.read().map_err(|_| {
+                let msg = format!("Failed to check {name}");
+                fail(ErrorCode::Internal, msg, &"Could not get read lock")
+            })?;
+            let reply = Reply::Checked {
+                name: name.clone(),
+                matched: checklist.explain(name),
             };
+            tracing::info!("{reply}");
+            Ok(reply)
+        }
+        Command::Reload => {
+            let ret = (|| -> Result<()> {
+                // Read the files without holding the locks so that queries are not blocked
+                let source = checklist
+                    .read()
+                    .map_err(|_| anyhow::anyhow!("Could not get read lock"))?
+                    .source();
+                let group_sources = client_groups
+                    .read()
+                    .map_err(|_| anyhow::anyhow!("Could not get read lock"))?
+                    .iter()
+                    .map(|x| x.checklist.source())
+                    .collect::<Vec<_>>();
+                let lists = source.load()?;
+                let group_lists = group_sources
+                    .iter()
+                    .map(|x| x.load())
+                    .collect::<local_dns_forwarder::Result<Vec<_>>>()?;
 
-            msg
+                // Replace all the lists at once, in the same lock order as the server
+                let mut groups = client_groups
+                    .write()
+                    .map_err(|_| anyhow::anyhow!("Could not get write lock"))?;
+                let mut checklist = checklist
+                    .write()
+                    .map_err(|_| anyhow::anyhow!("Could not get write lock"))?;
+                checklist.replace(lists);
+                for (group, lists) in groups.iter_mut().zip(group_lists) {
+                    group.checklist.replace(lists);
+                }
+                Ok(())
+            })();
+            match ret {
+                Ok(()) => {
+                    let reply = Reply::Reloaded;
+                    tracing::info!("{reply}");
+                    Ok(reply)
+                }
+                Err(e) => {
+                    let msg = format!("Failed to reload lists: {e}");
+                    tracing::error!("{msg}");
+                    Err(CommandError::new(ErrorCode::Failed, msg))
+                }
+            }
+        }
+        Command::Learned => {
+            let learned = learned.read().map_err(|_| {
+                let msg = "Failed to get learned FQDN(s)".to_string();
+                fail(ErrorCode::Internal, msg, &"Could not get read lock")
+            })?;
+            tracing::info!("Returned the list of learned FQDN(s)");
+            Ok(Reply::Learned {
+                names: learned.list().into_iter().cloned().collect(),
+            })
         }
-        "deny" => {
-            if splitted.len() < 2 {
-                return inv();
+        Command::Promote { names, all } | Command::Forget { names, all } => {
+            if !*all && names.is_empty() {
+                let msg = format!("Invalid command: {command}");
+                tracing::error!("{msg}");
+                return Err(CommandError::new(ErrorCode::InvalidCommand, msg));
             }
 
-            let fqdn = splitted[1];
-            let msg = if let Ok(mut checklist) = checklist.write() {
-                let msg = if checklist.allowlist.delete(fqdn) > 0 {
-                    format!("Remove {fqdn} from AllowList")
+            let names = {
+                let mut learned = learned.write().map_err(|_| {
+                    let msg = "Failed to get learned FQDN(s)".to_string();
+                    fail(ErrorCode::Internal, msg, &"Could not get write lock")
+                })?;
+                if *all {
+                    learned.take_all()
                 } else {
-                    format!("{fqdn} is not in AllowList")
-                };
-
-                tracing::info!("{msg}");
-                msg
-            } else {
-                let msg = format!("Failed to add {fqdn} to AllowList");
-                tracing::error!("{msg}");
-                msg
+                    names
+                        .iter()
+                        .filter_map(|x| learned.remove(&x.to_lowercase()))
+                        .collect()
+                }
             };
 
-            msg
+            if matches!(command, Command::Forget { .. }) {
+                let reply = Reply::Forgotten { count: names.len() };
+                tracing::info!("{reply}");
+                return Ok(reply);
+            }
+
+            let mut checklist = checklist.write().map_err(|_| {
+                let msg = "Failed to add learned FQDN(s) to AllowList".to_string();
+                fail(ErrorCode::Internal, msg, &"Could not get write lock")
+            })?;
+            let count = names
+                .iter()
+                .map(|x| checklist.allowlist.add(&x.name))
+                .sum::<usize>();
+            let reply = Reply::Promoted { count };
+            tracing::info!("{reply}");
+            Ok(reply)
         }
-        "save" => {
-            let msg = if let Ok(checklist) = checklist.read() {
-                match checklist.allowlist.save() {
-                    Ok(()) => {
-                        let msg = "AllowList is saved";
-                        tracing::info!("{msg}");
-                        msg.into()
-                    }
-                    Err(e) => {
-                        let msg = "Failed to save allowlist";
-                        tracing::error!("{msg}: {e}");
-                        msg.into()
-                    }
+        Command::Stats { report, limit } => {
+            let n = limit.unwrap_or(report.default_limit());
+            let stats = stats.read().map_err(|_| {
+                let msg = "Failed to get statistics".to_string();
+                fail(ErrorCode::Internal, msg, &"Could not get lock")
+            })?;
+            let counts = |v: Vec<(String, u64)>| {
+                v.into_iter()
+                    .map(|(key, count)| CountEntry { key, count })
+                    .collect::<Vec<_>>()
+            };
+            let entries = match report {
+                StatsReport::TopQueried => counts(
+                    stats
+                        .top_queried(n)
+                        .into_iter()
+                        .map(|(name, count)| (name.to_string(), count))
+                        .collect(),
+                ),
+                StatsReport::TopBlocked => counts(
+                    stats
+                        .top_blocked(n)
+                        .into_iter()
+                        .map(|(name, count)| (name.to_string(), count))
+                        .collect(),
+                ),
+                StatsReport::TopClients => counts(
+                    stats
+                        .top_clients(n)
+                        .into_iter()
+                        .map(|(client, count)| (client.to_string(), count))
+                        .collect(),
+                ),
+                StatsReport::Hourly => {
+                    let hours = stats
+                        .hourly(n)
+                        .into_iter()
+                        .map(|(hour, count)| HourEntry {
+                            hour,
+                            queries: count.queries,
+                            blocked: count.blocked,
+                        })
+                        .collect();
+                    tracing::info!("Returned the statistics ({report})");
+                    return Ok(Reply::Hourly { hours });
                 }
-            } else {
-                let msg = "Failed to save allowlist";
-                tracing::error!("{msg}: Could not get read lock");
-                msg.into()
             };
-            msg
+
+            tracing::info!("Returned the statistics ({report})");
+            Ok(Reply::Counts {
+                report: *report,
+                entries,
+            })
         }
-        "list" => {
-            let msg = if let Ok(checklist) = checklist.read() {
-                let mut names = Vec::with_capacity(checklist.allowlist.count());
-                for name in checklist.allowlist.iter() {
-                    names.push(name);
-                }
+        Command::StatsReset => {
+            let mut stats = stats.write().map_err(|_| {
+                let msg = "Failed to reset statistics".to_string();
+                fail(ErrorCode::Internal, msg, &"Could not get lock")
+            })?;
+            stats.reset();
+            let reply = Reply::StatsReset;
+            tracing::info!("{reply}");
+            Ok(reply)
+        }
+    }
+}
 
-                tracing::info!("Returned the list of FQDN(s)");
-                names.join("\n").to_string()
-            } else {
-                let msg = "Failed to get allowlist";
-                tracing::error!("{msg}: Could not get read lock");
-                msg.into()
-            };
-            msg
+fn list_ref(checklist: &CompositeCheckList, kind: ListKind) -> &CheckList {
+    match kind {
+        ListKind::Allow => &checklist.allowlist,
+        ListKind::Deny => &checklist.denylist,
+    }
+}
+
+fn list_mut(checklist: &mut CompositeCheckList, kind: ListKind) -> &mut CheckList {
+    match kind {
+        ListKind::Allow => &mut checklist.allowlist,
+        ListKind::Deny => &mut checklist.denylist,
+    }
+}
+
+/// Spawns a thread that removes expired temporary entries from the allowlist and the denylist
+fn spawn_expiry_watcher(checklist: Arc<RwLock<CompositeCheckList>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let has_expired = checklist
+            .read()
+            .map(|x| x.allowlist.has_expired() || x.denylist.has_expired())
+            .unwrap_or(false);
+        if !has_expired {
+            continue;
+        }
+
+        if let Ok(mut checklist) = checklist.write() {
+            for kind in [ListKind::Allow, ListKind::Deny] {
+                for name in list_mut(&mut checklist, kind).remove_expired() {
+                    tracing::info!("Remove {name} from {} (expired)", kind.label());
+                }
+            }
         }
-        _ => inv(),
+    });
+}
+
+/// Periodically logs the number of logs suppressed in the windows that have ended
+fn spawn_suppression_reporter(suppressor: Arc<RwLock<LogSuppressor>>) {
+    let (threshold, window) = match suppressor.read() {
+        Ok(v) => (v.threshold(), v.window()),
+        Err(_) => return,
+    };
+    if threshold == 0 {
+        return;
     }
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(window.min(Duration::from_secs(60)));
+        let summary = match suppressor.write() {
+            Ok(mut suppressor) => suppressor.flush(),
+            Err(_) => {
+                tracing::error!("Failed to get log suppressor(write lock error)");
+                continue;
+            }
+        };
+        for (key, count) in summary {
+            tracing::info!(target: QUERY_TARGET, "{key}: suppressed {count} repeats");
+        }
+    });
 }
 
-async fn exec(
+fn exec(
     config: InnerConfig,
     reload_handle: local_dns_forwarder::logger::ReloadHandle,
 ) -> Result<()> {
@@ -373,46 +1394,488 @@ async fn exec(
     tracing::info!("[Config] Server: {}", config.server);
 
     let checklist = get_checklist(&config)?;
-    let addr = "127.0.0.1:60001"
-        .parse()
-        .expect("Failed to parse endpoint for ipctl Server");
+    let client_groups = get_client_groups(&config)?;
+
+    tracing::info!(
+        "[Config] Log Suppression: {} per {}",
+        config.log_threshold,
+        humantime::format_duration(config.log_window)
+    );
+    let suppressor = Arc::new(RwLock::new(LogSuppressor::new(
+        config.log_threshold,
+        config.log_window,
+    )));
+    spawn_suppression_reporter(Arc::clone(&suppressor));
+    let mut event = LDFResolveEvent::new(
+        suppressor,
+        config.output_allowed_log,
+        config.output_nochecked_log,
+    );
+    let _json_guard = if let Some(output) = config.json_log {
+        let log_dir = match output {
+            JsonLogOutput::Stdout => None,
+            JsonLogOutput::File => config.log_dir.as_ref(),
+        };
+        tracing::info!("[Config] JSON Query Log: {output:?}");
+        let (writer, guard) = logger::json_writer(log_dir, config.log_policy.rotation);
+        event = event.json(JsonResolveEvent::new(writer));
+        Some(guard)
+    } else {
+        None
+    };
+
+    let metrics = if let Some(addr) = config.metrics_address {
+        tracing::info!("[Config] Metrics: http://{addr}/metrics");
+        let metrics = Arc::new(Metrics::new()?);
+        event = event.metrics(Arc::clone(&metrics));
+        Some((addr, metrics))
+    } else {
+        None
+    };
+
+    let stats = Arc::new(RwLock::new(Stats::new()));
+    event = event.stats(Arc::clone(&stats));
+
+    if let Some((path, retention)) = config.history.as_ref() {
+        tracing::info!(
+            "[Config] History: {} (Retention: {})",
+            path.display(),
+            humantime::format_duration(*retention)
+        );
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        event = event.history(HistoryResolveEvent::spawn(path, *retention)?);
+    }
 
     let server = Server::from_config(config.server)
         .checklist(checklist)
-        .event(LDFResolveEvent::new(
-            3,
-            config.output_allowed_log,
-            config.output_nochecked_log,
-        ))
+        .client_groups(client_groups)
+        .event(event)
         .build();
 
+    if let Some((addr, metrics)) = metrics {
+        metrics::spawn_server(addr, metrics, Arc::clone(&server.checklist))?;
+    }
+
+    if !config.subscriptions.is_empty() {
+        std::fs::create_dir_all(&config.cache_dir)?;
+        subscription::spawn_updater(config.subscriptions, Arc::clone(&server.checklist));
+    }
+
+    spawn_expiry_watcher(Arc::clone(&server.checklist));
+
     let checklist = Arc::clone(&server.checklist);
-    let handler =
-        ipctl::Server::new(move |x: &str| on_ipctl(x, &reload_handle, Arc::clone(&checklist)))
-            .spawn_and_serve(addr);
+    let client_groups = Arc::clone(&server.client_groups);
+    let learned = Arc::clone(&server.learned);
+    tracing::info!(
+        "[Config] Control: {} (Token: {})",
+        config.control.address,
+        config.control.token.is_some()
+    );
+    control::spawn(config.control, move |caller, x| {
+        on_control(
+            caller,
+            x,
+            &reload_handle,
+            Arc::clone(&checklist),
+            Arc::clone(&client_groups),
+            Arc::clone(&learned),
+            Arc::clone(&stats),
+        )
+    })?;
     tracing::info!("Start Local DNS Forwarder");
     server.serve()?;
+    Ok(())
+}
+
+/// Parses "YYYY-MM-DD HH:MM[:SS]" or "YYYY-MM-DD" as a local time
+fn parse_local_time(text: &str) -> Result<chrono::DateTime<chrono::Local>> {
+    use chrono::{NaiveDate, NaiveDateTime, TimeZone};
+    let text = text.trim();
+    let datetime = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|x| x.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .map_err(|e| anyhow::anyhow!("Invalid time {text} ({e})"))?;
+    chrono::Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .ok_or_else(|| anyhow::anyhow!("Invalid local time {text}"))
+}
 
-    handler.join().await?;
+fn search_history(config: &InnerConfig, args: HistoryArgs) -> Result<()> {
+    let Some((path, _)) = config.history.as_ref() else {
+        anyhow::bail!("[history] is not configured");
+    };
+    let filter = HistoryFilter {
+        from: args.from.as_deref().map(parse_local_time).transpose()?,
+        to: args.to.as_deref().map(parse_local_time).transpose()?,
+        client: args.client,
+        name: args.name,
+        decision: args.decision,
+        limit: Some(args.limit),
+    };
+    let history = History::open_read_only(path)?;
+    for v in history.search(&filter)? {
+        let mut line = format!(
+            "{} {} <{}> {} {} {} {:.1}ms",
+            v.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+            v.client,
+            v.qtype,
+            v.qname,
+            v.decision,
+            v.rcode,
+            v.latency_ms
+        );
+        if let Some(rule) = v.rule.as_ref() {
+            line.push_str(&format!(" ({rule})"));
+        }
+        if !v.answers.is_empty() {
+            line.push_str(&format!(" {}", v.answers));
+        }
+        println!("{line}");
+    }
     Ok(())
 }
 
+/// Sends the command to the running instance, prints the result and returns the exit code.
+/// The config is read only if the address is not given or `config_path` is specified,
+/// and its token is used only if none is given by the options or the environment.
+fn run_ctl(config_path: Option<&Path>, args: CtlArgs) -> i32 {
+    let (address, token) = match ctl_target(config_path, &args) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return EXIT_FAILED;
+        }
+    };
+    let ret = control::send(&address, token.as_deref(), args.command.into());
+    let ret = match ret {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Failed to send the command to {address}: {e}");
+            return EXIT_UNAVAILABLE;
+        }
+    };
+
+    let code = if ret.is_ok() { 0 } else { EXIT_FAILED };
+    if args.json {
+        let resp = Response::new(None, ret);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&resp).unwrap_or_default()
+        );
+        return code;
+    }
+    match ret {
+        Ok(reply) => {
+            let text = reply.to_string();
+            if !text.is_empty() {
+                println!("{text}");
+            }
+        }
+        Err(e) => eprintln!("{e}"),
+    }
+    code
+}
+
+/// Errors and warnings found by `check-config`
+#[derive(Debug, Default)]
+struct ConfigCheck {
+    errors: usize,
+    warnings: usize,
+}
+
+impl ConfigCheck {
+    fn error(&mut self, message: impl std::fmt::Display) {
+        self.errors += 1;
+        println!("  Error: {message}");
+    }
+
+    fn warn(&mut self, message: impl std::fmt::Display) {
+        self.warnings += 1;
+        println!("  Warning: {message}");
+    }
+
+    /// Reads the list and reports invalid and duplicate entries
+    fn list(&mut self, label: &str, path: &Path, format: Option<ListFormat>) {
+        let report = match ListReport::check(path, format) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("[Config] {label}: {}", path.display());
+                self.error(format!("Failed to read {} ({e})", path.display()));
+                return;
+            }
+        };
+        let mut line = format!("[Config] {label}: {} (", path.display());
+        if let Some(format) = report.format {
+            line.push_str(&format!("{format}, "));
+        }
+        line.push_str(&format!("{} FQDN(s)", report.count));
+        if report.skipped > 0 {
+            line.push_str(&format!(", {} line(s) skipped", report.skipped));
+        }
+        println!("{line})");
+
+        for (i, issue) in report.issues.iter().enumerate() {
+            if i == MAX_LIST_ISSUES {
+                let rest = &report.issues[i..];
+                self.errors += rest.iter().filter(|x| x.is_error()).count();
+                self.warnings += rest.iter().filter(|x| !x.is_error()).count();
+                println!("  ... and {} more", rest.len());
+                break;
+            }
+            if issue.is_error() {
+                self.error(issue);
+            } else {
+                self.warn(issue);
+            }
+        }
+    }
+
+    /// Reports a directory that cannot be created or used
+    fn dir(&mut self, label: &str, path: &Path) {
+        println!("[Config] {label}: {}", path.display());
+        if path.is_dir() {
+            return;
+        }
+        if path.exists() {
+            self.error(format!("{} is not a directory", path.display()));
+            return;
+        }
+        match path.ancestors().skip(1).find(|x| x.exists()) {
+            Some(parent) if !parent.is_dir() => {
+                self.error(format!("{} is not a directory", parent.display()));
+            }
+            _ => self.warn(format!(
+                "{} does not exist and will be created",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Loads the config and every list, and prints the effective config and the problems found.
+/// Nothing is bound or written, so that it can be run before deploying the config.
+fn check_config(config_path: &Path) -> i32 {
+    use std::net::ToSocketAddrs;
+    println!("[Config] Config path: {}", config_path.display());
+    let config = match Config::load(config_path).and_then(InnerConfig::new) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("  Error: {e}");
+            println!("1 error(s), 0 warning(s)");
+            return EXIT_FAILED;
+        }
+    };
+
+    let mut check = ConfigCheck::default();
+    println!("[Config] Log Level: {}", config.loglevel);
+    println!("[Config] Query Log Level: {}", config.query_loglevel);
+    println!("[Config] Log Output: {}", config.log_output);
+    match config.log_dir.as_ref() {
+        Some(log_dir) => {
+            check.dir("Log Dir", log_dir);
+            println!("[Config] Log Policy: {}", config.log_policy);
+        }
+        None => println!("[Config] Log Dir: None"),
+    }
+    println!("[Config] Output Allowed Log: {}", config.output_allowed_log);
+    println!(
+        "[Config] Output NoChecked Log: {}",
+        config.output_nochecked_log
+    );
+    println!(
+        "[Config] Log Suppression: {} per {}",
+        config.log_threshold,
+        humantime::format_duration(config.log_window)
+    );
+    if let Some(output) = config.json_log {
+        println!("[Config] JSON Query Log: {output:?}");
+    }
+
+    println!("[Config] Server: {}", config.server);
+    let (address, port) = config.server.bind_address();
+    if let Err(e) = (address, port).to_socket_addrs() {
+        check.error(format!("Invalid server address {address}:{port} ({e})"));
+    }
+    if let Some(addr) = config.metrics_address {
+        println!("[Config] Metrics: http://{addr}/metrics");
+    }
+    if let Some((path, retention)) = config.history.as_ref() {
+        println!(
+            "[Config] History: {} (Retention: {})",
+            path.display(),
+            humantime::format_duration(*retention)
+        );
+        if let Some(dir) = path.parent() {
+            check.dir("History Dir", dir);
+        }
+    }
+    println!(
+        "[Config] Control: {} (Token: {})",
+        config.control.address,
+        config.control.token.is_some()
+    );
+    match &config.control.address {
+        ControlAddress::Unix(path) => {
+            println!("[Config] Control Socket Mode: {:04o}", config.control.mode);
+            if let Some(dir) = path.parent() {
+                check.dir("Control Socket Dir", dir);
+            }
+        }
+        ControlAddress::Tcp(addr) => {
+            if !addr.ip().is_loopback() && config.control.token.is_none() {
+                check.warn(format!(
+                    "{addr} is not a loopback address, but no token is set"
+                ));
+            }
+        }
+    }
+
+    match config.allowlist.as_ref() {
+        Some(path) => check.list("AllowList", path, None),
+        None => println!("[Config] AllowList: None"),
+    }
+    match config.denylist.as_ref() {
+        Some(path) => check.list("DenyList", path, None),
+        None => println!("[Config] DenyList: None"),
+    }
+    for (path, format) in config.blocklists.iter() {
+        check.list("BlockList", path, Some(*format));
+    }
+    for v in config.scheduled_lists.iter() {
+        let schedules = v
+            .schedules
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        let label = format!(
+            "ScheduledList {} ({}, {})",
+            v.name,
+            v.kind,
+            schedules.join(", ")
+        );
+        check.list(&label, &v.path, None);
+    }
+    if !config.subscriptions.is_empty() {
+        check.dir("Cache Dir", &config.cache_dir);
+    }
+    for subscription in config.subscriptions.iter() {
+        let label = format!("Subscription {} ({})", subscription.name, subscription.url);
+        if subscription.cache_path().exists() {
+            check.list(&label, subscription.cache_path(), Some(subscription.format));
+        } else {
+            println!("[Config] {label}: not cached yet");
+        }
+    }
+    for group in config.client_groups.iter() {
+        let networks = group
+            .networks
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        println!(
+            "[Config] ClientGroup: {} ({}, default: {})",
+            group.name,
+            networks.join(", "),
+            group.default_action
+        );
+        let name = &group.name;
+        if let Some(path) = group.allowlist.as_ref() {
+            check.list(&format!("ClientGroup {name} AllowList"), path, None);
+        }
+        if let Some(path) = group.denylist.as_ref() {
+            check.list(&format!("ClientGroup {name} DenyList"), path, None);
+        }
+        for (path, format) in group.blocklists.iter() {
+            check.list(
+                &format!("ClientGroup {name} BlockList"),
+                path,
+                Some(*format),
+            );
+        }
+    }
+
+    println!("{} error(s), {} warning(s)", check.errors, check.warnings);
+    if check.errors > 0 {
+        EXIT_FAILED
+    } else {
+        0
+    }
+}
+
+/// Returns the address and the token to send the command with
+fn ctl_target(
+    config_path: Option<&Path>,
+    args: &CtlArgs,
+) -> Result<(ControlAddress, Option<String>)> {
+    let mut token = match (args.token.as_ref(), args.token_file.as_ref()) {
+        (Some(token), _) => Some(token.clone()),
+        (None, Some(path)) => Some(read_token_file(path)?),
+        (None, None) => std::env::var(CONTROL_TOKEN_ENV).ok(),
+    };
+    if token.as_deref() == Some("") {
+        anyhow::bail!("The control token is empty");
+    }
+
+    let config = match (args.address.as_ref(), config_path) {
+        (Some(_), None) => None,
+        (_, Some(path)) => Some(CtlConfig::load(path)?),
+        (None, None) => Some(CtlConfig::load(DEFAULT_CONFIG_PATH)?),
+    };
+    let address = match (args.address.as_deref(), config.as_ref()) {
+        (Some(v), _) => v.parse::<ControlAddress>()?,
+        (None, Some(config)) => config.address()?,
+        (None, None) => ControlAddress::default(),
+    };
+    if token.is_none() {
+        if let Some(config) = config.as_ref() {
+            token = config.token()?;
+        }
+    }
+    Ok((address, token))
+}
+
 fn exit<R>(e: anyhow::Error) -> R {
     eprintln!("{e}");
     std::process::exit(1);
 }
 
-#[tokio::main]
-async fn main() {
-    let version = format!("ldf ({}) - {}", get_build_mode(), get_version());
-    println!("{version}");
+fn main() {
     let cli = Cli::parse();
     let config_path = get_config_path(&cli).unwrap_or_else(exit);
+    if let Some(SubCommand::Ctl(args)) = cli.command {
+        // The output is kept to the result so that it can be used in scripts
+        let config_path = cli.config.is_some().then_some(config_path.as_path());
+        std::process::exit(run_ctl(config_path, args));
+    }
+    if let Some(SubCommand::CheckConfig) = cli.command {
+        std::process::exit(check_config(&config_path));
+    }
+
+    let version = format!("ldf ({}) - {}", get_build_mode(), get_version());
+    println!("{version}");
     println!("[Config] Config path: {}", config_path.display());
     let config = Config::load(config_path).unwrap_or_else(exit);
     let config = InnerConfig::new(config).unwrap_or_else(exit);
-    let log = logger::init(config.loglevel, config.log_dir.as_ref());
+    if let Some(SubCommand::History(args)) = cli.command {
+        search_history(&config, args).unwrap_or_else(exit);
+        return;
+    }
+    let log = logger::init(
+        config.loglevel,
+        config.query_loglevel,
+        config.log_dir.as_ref(),
+        config.log_policy.clone(),
+        config.log_output.clone(),
+    )
+    .unwrap_or_else(|e| exit(e.into()));
     println!("[Config] Log Level: {}", config.loglevel);
+    println!("[Config] Query Log Level: {}", config.query_loglevel);
+    println!("[Config] Log Output: {}", config.log_output);
     tracing::trace!("Log Level: Trace");
     tracing::debug!("Log Level: Debug");
     tracing::info!("Log Level: Info");
@@ -421,16 +1884,17 @@ async fn main() {
     if let Err(e) = log.remove_old_logs() {
         tracing::error!("{e}");
     }
+    log.spawn_maintainer();
 
     let code = {
         let LogContext {
             reload_handle,
-            file_guard: _file_guard,
+            file_guards: _file_guards,
             ..
         } = log;
 
         tracing::info!("{version}");
-        match exec(config, reload_handle).await {
+        match exec(config, reload_handle) {
             Ok(_) => 0,
             Err(e) => {
                 tracing::error!(
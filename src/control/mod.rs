@@ -0,0 +1,558 @@
+mod protocol;
+
+pub use protocol::{
+    Command, CommandError, CountEntry, ErrorCode, HourEntry, ListEntry, Reply, Request, Response,
+    StatsReport, PROTOCOL_VERSION,
+};
+
+use crate::error::{Error, Result};
+use crate::logger::AUDIT_TARGET;
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Maximum length of a request line
+const MAX_LINE: u64 = 64 * 1024;
+/// Maximum number of connections handled at the same time
+const MAX_CONNECTIONS: usize = 16;
+/// Time to wait for a request, after which an idle connection is closed
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time for `send` to wait for the response, which may take a while for commands such as `reload`
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Number of unauthorized JSON requests after which the connection is closed
+const MAX_UNAUTHORIZED: usize = 3;
+
+/// Address the control server listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Default for ControlAddress {
+    fn default() -> Self {
+        Self::Tcp(([127, 0, 0, 1], 60001).into())
+    }
+}
+
+impl FromStr for ControlAddress {
+    type Err = Error;
+
+    /// Parses "unix:/run/ldf/control.sock", "tcp:127.0.0.1:60001" or "127.0.0.1:60001"
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.strip_prefix("tcp:")
+            .unwrap_or(s)
+            .parse()
+            .map(Self::Tcp)
+            .map_err(|_| Error::InvalidControlAddress(s.to_string()))
+    }
+}
+
+impl Display for ControlAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp:{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Peer of a control connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    /// Process connected to the unix socket, if its credentials are available
+    Unix {
+        uid: Option<u32>,
+        pid: Option<i32>,
+    },
+    Tcp(SocketAddr),
+}
+
+impl Display for Caller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unix { uid, pid } => {
+                write!(f, "unix(uid=")?;
+                match uid {
+                    Some(v) => write!(f, "{v}")?,
+                    None => write!(f, "?")?,
+                }
+                write!(f, ", pid=")?;
+                match pid {
+                    Some(v) => write!(f, "{v}")?,
+                    None => write!(f, "?")?,
+                }
+                write!(f, ")")
+            }
+            Self::Tcp(addr) => write!(f, "tcp({addr})"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn peer_credentials(stream: &UnixStream) -> (Option<u32>, Option<i32>) {
+    use std::os::fd::AsRawFd;
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `cred` and `len` are valid for writes and `len` is the size of `cred`
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == 0 {
+        (Some(cred.uid), Some(cred.pid))
+    } else {
+        (None, None)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_credentials(_stream: &UnixStream) -> (Option<u32>, Option<i32>) {
+    (None, None)
+}
+
+/// Settings of the control server
+#[derive(Debug, Clone)]
+pub struct ControlConfig {
+    pub address: ControlAddress,
+    /// Token that clients have to send before the command
+    pub token: Option<String>,
+    /// Permissions of the unix socket
+    pub mode: u32,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            address: ControlAddress::default(),
+            token: None,
+            mode: 0o600,
+        }
+    }
+}
+
+/// Compares the tokens in a time that does not depend on where they differ
+fn token_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Runs the command given by the handler and returns the result
+type CommandResult = std::result::Result<Reply, CommandError>;
+
+/// Reads requests and writes the responses.
+///
+/// If the first line is a JSON object, the connection uses the JSON protocol,
+/// and each line is a `Request` answered with a `Response` until the client closes it.
+/// Otherwise the line is a text command, preceded by `auth <token>` if a token is required,
+/// and the connection is closed after the response is written.
+fn handle<S, F>(stream: &S, caller: &Caller, token: Option<&str>, handler: &F) -> Result<()>
+where
+    for<'a> &'a S: Read + Write,
+    F: Fn(&Caller, &Command) -> CommandResult,
+{
+    let mut reader = BufReader::new(stream);
+    let mut read_line = || -> Result<String> {
+        let mut line = String::new();
+        (&mut reader).take(MAX_LINE).read_line(&mut line)?;
+        Ok(line.trim().to_string())
+    };
+
+    let mut writer = stream;
+    let mut line = read_line()?;
+    if line.starts_with('{') {
+        let mut unauthorized = 0;
+        while !line.is_empty() {
+            let resp = handle_json(&line, caller, token, handler);
+            if resp
+                .error
+                .as_ref()
+                .is_some_and(|x| x.code == ErrorCode::Unauthorized)
+            {
+                unauthorized += 1;
+            }
+            let mut resp = serde_json::to_string(&resp).map_err(std::io::Error::from)?;
+            resp.push('\n');
+            writer.write_all(resp.as_bytes())?;
+            writer.flush()?;
+            if unauthorized >= MAX_UNAUTHORIZED {
+                tracing::warn!(target: AUDIT_TARGET, "[Audit] {caller}: Disconnected (too many unauthorized requests)");
+                return Ok(());
+            }
+            line = read_line()?;
+        }
+        return Ok(());
+    }
+
+    let mut authorized = token.is_none();
+    if let Some(v) = line.strip_prefix("auth ") {
+        authorized = token.is_none_or(|x| token_eq(x, v.trim()));
+        line = read_line()?;
+    }
+
+    if !authorized {
+        tracing::warn!(target: AUDIT_TARGET, "[Audit] {caller}: Rejected (invalid or missing token)");
+        writer.write_all(b"Unauthorized\n")?;
+        return Ok(());
+    }
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let mut resp = match Command::parse(&line) {
+        Ok(command) => match handler(caller, &command) {
+            Ok(v) => v.to_string(),
+            Err(e) => e.message,
+        },
+        Err(e) => {
+            tracing::error!("{e}");
+            e.message
+        }
+    };
+    if !resp.ends_with('\n') {
+        resp.push('\n');
+    }
+    writer.write_all(resp.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn handle_json<F>(line: &str, caller: &Caller, token: Option<&str>, handler: &F) -> Response
+where
+    F: Fn(&Caller, &Command) -> CommandResult,
+{
+    let req = match serde_json::from_str::<Request>(line) {
+        Ok(v) => v,
+        Err(e) => {
+            let e = CommandError::new(ErrorCode::InvalidRequest, format!("Invalid request: {e}"));
+            return Response::new(None, Err(e));
+        }
+    };
+    if req.version != PROTOCOL_VERSION {
+        let e = CommandError::new(
+            ErrorCode::UnsupportedVersion,
+            format!(
+                "Unsupported version {} (supported: {PROTOCOL_VERSION})",
+                req.version
+            ),
+        );
+        return Response::new(req.id, Err(e));
+    }
+    let authorized = match token {
+        Some(token) => req.token.as_deref().is_some_and(|x| token_eq(token, x)),
+        None => true,
+    };
+    if !authorized {
+        tracing::warn!(target: AUDIT_TARGET, "[Audit] {caller}: Rejected (invalid or missing token)");
+        let e = CommandError::new(ErrorCode::Unauthorized, "Unauthorized");
+        return Response::new(req.id, Err(e));
+    }
+
+    Response::new(req.id, handler(caller, &req.command))
+}
+
+/// Starts the control server in the background.
+/// `handler` receives the caller and the command, and returns the result.
+pub fn spawn<F>(config: ControlConfig, handler: F) -> Result<JoinHandle<()>>
+where
+    F: Fn(&Caller, &Command) -> CommandResult + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let token = config.token.map(Arc::<str>::from);
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let ret = match config.address {
+        ControlAddress::Tcp(addr) => {
+            if !addr.ip().is_loopback() && token.is_none() {
+                tracing::warn!("The control server on {addr} accepts commands without a token");
+            }
+            let listener = TcpListener::bind(addr)?;
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(v) => v,
+                        Err(e) => {
+                            tracing::error!("Failed to accept control connection ({e})");
+                            continue;
+                        }
+                    };
+                    let caller = match stream.peer_addr() {
+                        Ok(v) => Caller::Tcp(v),
+                        Err(_) => continue,
+                    };
+                    serve::<TcpStream, F>(stream, caller, &token, &handler, &connections);
+                }
+            })
+        }
+        ControlAddress::Unix(path) => {
+            if let Ok(meta) = std::fs::symlink_metadata(&path) {
+                if !meta.file_type().is_socket() {
+                    return Err(Error::InvalidControlAddress(format!(
+                        "{} exists and is not a socket",
+                        path.display()
+                    )));
+                }
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(Error::InvalidControlAddress(format!(
+                        "{} is used by another process",
+                        path.display()
+                    )));
+                }
+                // Left by the previous process
+                std::fs::remove_file(&path)?;
+            }
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let listener = bind_unix(&path, config.mode)?;
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(v) => v,
+                        Err(e) => {
+                            tracing::error!("Failed to accept control connection ({e})");
+                            continue;
+                        }
+                    };
+                    let (uid, pid) = peer_credentials(&stream);
+                    let caller = Caller::Unix { uid, pid };
+                    serve::<UnixStream, F>(stream, caller, &token, &handler, &connections);
+                }
+            })
+        }
+    };
+    Ok(ret)
+}
+
+/// Binds the socket in a private directory and moves it to `path` after its permissions are set,
+/// so that no one can connect to it with the default permissions
+fn bind_unix(path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let private_dir = dir.join(format!(".ldf-control-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&private_dir);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)?;
+    let ret = (|| {
+        let tmp_path = private_dir.join("control.sock");
+        let listener = UnixListener::bind(&tmp_path)?;
+        std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(listener)
+    })();
+    let _ = std::fs::remove_dir_all(&private_dir);
+    ret
+}
+
+/// Handles the connection on its own thread
+fn serve<S, F>(
+    stream: S,
+    caller: Caller,
+    token: &Option<Arc<str>>,
+    handler: &Arc<F>,
+    connections: &Arc<AtomicUsize>,
+) where
+    S: ControlStream,
+    for<'a> &'a S: Read + Write,
+    F: Fn(&Caller, &Command) -> CommandResult + Send + Sync + 'static,
+{
+    if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        connections.fetch_sub(1, Ordering::SeqCst);
+        tracing::warn!("Too many control connections, {caller} is refused");
+        return;
+    }
+    let token = token.clone();
+    let handler = Arc::clone(handler);
+    let connections = Arc::clone(connections);
+    std::thread::spawn(move || {
+        let _ = stream.set_timeout(READ_TIMEOUT);
+        if let Err(e) = handle(&stream, &caller, token.as_deref(), handler.as_ref()) {
+            tracing::warn!("Control connection from {caller} failed ({e})");
+        }
+        connections.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+/// Sends the command to the control server at `address` with the JSON protocol and returns the result
+pub fn send(
+    address: &ControlAddress,
+    token: Option<&str>,
+    command: Command,
+) -> Result<CommandResult> {
+    let request = Request::new(command, token.map(|x| x.to_string()));
+    match address {
+        ControlAddress::Tcp(addr) => exchange(TcpStream::connect(addr)?, &request),
+        ControlAddress::Unix(path) => exchange(UnixStream::connect(path)?, &request),
+    }
+}
+
+fn exchange<S>(stream: S, request: &Request) -> Result<CommandResult>
+where
+    S: ControlStream,
+    for<'a> &'a S: Read + Write,
+{
+    stream.set_timeout(RESPONSE_TIMEOUT)?;
+    let mut line = serde_json::to_string(request).map_err(std::io::Error::from)?;
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+    (&stream).flush()?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.trim().is_empty() {
+        return Err(Error::InvalidResponse("Connection closed".into()));
+    }
+    let response = serde_json::from_str::<Response>(&line)
+        .map_err(|e| Error::InvalidResponse(e.to_string()))?;
+    Ok(response.into_result())
+}
+
+/// Stream of a control connection
+trait ControlStream: Send + 'static {
+    fn set_timeout(&self, timeout: Duration) -> std::io::Result<()>;
+}
+
+impl ControlStream for TcpStream {
+    fn set_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+}
+
+impl ControlStream for UnixStream {
+    fn set_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn request(path: &std::path::Path, text: &str) -> String {
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream.write_all(text.as_bytes()).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut ret = String::new();
+        stream.read_to_string(&mut ret).unwrap();
+        ret
+    }
+
+    #[test]
+    fn test_control() {
+        assert_eq!(
+            ControlAddress::Unix(PathBuf::from("/run/ldf/control.sock")),
+            "unix:/run/ldf/control.sock".parse().unwrap()
+        );
+        assert_eq!(
+            ControlAddress::Tcp(([127, 0, 0, 1], 60001).into()),
+            "tcp:127.0.0.1:60001".parse().unwrap()
+        );
+        assert_eq!(
+            ControlAddress::default(),
+            "127.0.0.1:60001".parse().unwrap()
+        );
+        assert!("localhost".parse::<ControlAddress>().is_err());
+
+        let path =
+            std::env::temp_dir().join(format!("ldf-test-control-{}.sock", std::process::id()));
+        let config = ControlConfig {
+            address: ControlAddress::Unix(path.clone()),
+            token: Some("secret".into()),
+            mode: 0o600,
+        };
+        spawn(config.clone(), |caller, command| match command {
+            Command::Check { name } => Ok(Reply::Checked {
+                name: format!("{caller} {name}"),
+                matched: None,
+            }),
+            _ => Err(CommandError::new(ErrorCode::Failed, "Failed")),
+        })
+        .unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        // The socket of a running instance is not replaced
+        assert!(spawn(config, |_, _| Ok(Reply::Reloaded)).is_err());
+
+        // SAFETY: `getuid` has no preconditions and always succeeds
+        let uid = unsafe { libc::getuid() };
+        let caller = format!("unix(uid={uid}, pid={})", std::process::id());
+        let resp = request(&path, "auth secret\ncheck example.com\n");
+        assert_eq!(format!("{caller} example.com: NotFound\n"), resp);
+        assert_eq!("Failed\n", request(&path, "auth secret\nreload\n"));
+        assert_eq!(
+            "Invalid command: foo\n",
+            request(&path, "auth secret\nfoo\n")
+        );
+        assert_eq!("Unauthorized\n", request(&path, "auth wrong\nreload\n"));
+        assert_eq!("Unauthorized\n", request(&path, "reload\n"));
+
+        let resp = request(
+            &path,
+            concat!(
+                r#"{"version":1,"id":1,"token":"secret","command":"check","name":"a.com"}"#,
+                "\n",
+                r#"{"version":1,"id":2,"command":"reload"}"#,
+                "\n",
+                r#"{"version":2,"id":3,"token":"secret","command":"reload"}"#,
+                "\n",
+                "{\n",
+            ),
+        );
+        let resp = resp
+            .lines()
+            .map(|x| serde_json::from_str::<Response>(x).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(4, resp.len());
+        assert_eq!(Some(serde_json::json!(1)), resp[0].id);
+        assert_eq!(
+            format!("{caller} a.com: NotFound"),
+            resp[0].clone().into_result().unwrap().to_string()
+        );
+        let code = |x: &Response| x.clone().into_result().unwrap_err().code;
+        assert_eq!(ErrorCode::Unauthorized, code(&resp[1]));
+        assert_eq!(ErrorCode::UnsupportedVersion, code(&resp[2]));
+        assert_eq!(ErrorCode::InvalidRequest, code(&resp[3]));
+
+        // The connection is closed after too many unauthorized requests
+        let unauthorized = format!("{}\n", r#"{"version":1,"command":"reload"}"#);
+        let resp = request(&path, &unauthorized.repeat(MAX_UNAUTHORIZED + 2));
+        assert_eq!(MAX_UNAUTHORIZED, resp.lines().count());
+
+        let address = ControlAddress::Unix(path.clone());
+        let command = Command::Check {
+            name: "b.com".into(),
+        };
+        let reply = send(&address, Some("secret"), command.clone()).unwrap();
+        assert_eq!(
+            format!("{caller} b.com: NotFound"),
+            reply.unwrap().to_string()
+        );
+        let e = send(&address, None, command).unwrap().unwrap_err();
+        assert_eq!(ErrorCode::Unauthorized, e.code);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
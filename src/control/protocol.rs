@@ -0,0 +1,673 @@
+use crate::filters::{CheckMatch, ListKind};
+use crate::learned_names::LearnedName;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Version of the JSON protocol
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Command sent to the control server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Changes the level of the diagnostic log
+    Log {
+        level: String,
+    },
+    /// Adds the name to the allowlist, for the duration if specified
+    Allow {
+        name: String,
+        #[serde(default, with = "humantime_option")]
+        duration: Option<Duration>,
+    },
+    /// Adds the name to the denylist, for the duration if specified
+    Deny {
+        name: String,
+        #[serde(default, with = "humantime_option")]
+        duration: Option<Duration>,
+    },
+    Remove {
+        list: ListKind,
+        name: String,
+    },
+    List {
+        #[serde(default = "default_list")]
+        list: ListKind,
+    },
+    /// Writes the list to its file, or both lists if `list` is not specified
+    Save {
+        #[serde(default)]
+        list: Option<ListKind>,
+    },
+    /// Shows which list and entry match the name
+    Check {
+        name: String,
+    },
+    Reload,
+    Learned,
+    /// Adds the learned names to the allowlist
+    Promote {
+        #[serde(default)]
+        names: Vec<String>,
+        #[serde(default)]
+        all: bool,
+    },
+    /// Discards the learned names
+    Forget {
+        #[serde(default)]
+        names: Vec<String>,
+        #[serde(default)]
+        all: bool,
+    },
+    Stats {
+        report: StatsReport,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    StatsReset,
+}
+
+fn default_list() -> ListKind {
+    ListKind::Allow
+}
+
+/// Statistics returned by `Command::Stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatsReport {
+    TopQueried,
+    TopBlocked,
+    TopClients,
+    Hourly,
+}
+
+impl StatsReport {
+    pub fn default_limit(self) -> usize {
+        match self {
+            Self::Hourly => 24,
+            _ => 10,
+        }
+    }
+}
+
+impl Display for StatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TopQueried => write!(f, "top-queried"),
+            Self::TopBlocked => write!(f, "top-blocked"),
+            Self::TopClients => write!(f, "top-clients"),
+            Self::Hourly => write!(f, "hourly"),
+        }
+    }
+}
+
+fn parse_list_kind(v: &str) -> Option<ListKind> {
+    match v.to_lowercase().as_ref() {
+        "allow" | "allowlist" => Some(ListKind::Allow),
+        "deny" | "denylist" => Some(ListKind::Deny),
+        _ => None,
+    }
+}
+
+impl Command {
+    /// Parses a text command such as `allow www.example.com 30m`
+    pub fn parse(text: &str) -> Result<Self, CommandError> {
+        let inv = || {
+            CommandError::new(
+                ErrorCode::InvalidCommand,
+                format!("Invalid command: {text}"),
+            )
+        };
+        let splitted = text.split(' ').collect::<Vec<_>>();
+        let arg = |i: usize| splitted.get(i).copied().ok_or_else(inv);
+
+        let ret = match splitted[0].to_lowercase().as_ref() {
+            "log" => Self::Log {
+                level: arg(1)?.to_string(),
+            },
+            "allow" | "deny" => {
+                let name = arg(1)?.to_string();
+                let duration = match splitted.get(2) {
+                    Some(v) => Some(humantime::parse_duration(v).map_err(|_| {
+                        CommandError::new(
+                            ErrorCode::InvalidArgument,
+                            format!("Failed to convert {v} to duration"),
+                        )
+                    })?),
+                    None => None,
+                };
+                if splitted[0].eq_ignore_ascii_case("allow") {
+                    Self::Allow { name, duration }
+                } else {
+                    Self::Deny { name, duration }
+                }
+            }
+            "remove" => Self::Remove {
+                list: parse_list_kind(arg(1)?).ok_or_else(inv)?,
+                name: arg(2)?.to_string(),
+            },
+            "save" => Self::Save {
+                list: match splitted.get(1) {
+                    Some(v) => Some(parse_list_kind(v).ok_or_else(inv)?),
+                    None => None,
+                },
+            },
+            "list" => Self::List {
+                list: match splitted.get(1) {
+                    Some(v) => parse_list_kind(v).ok_or_else(inv)?,
+                    None => ListKind::Allow,
+                },
+            },
+            "check" | "explain" => Self::Check {
+                name: arg(1)?.to_string(),
+            },
+            "reload" => Self::Reload,
+            "learned" => Self::Learned,
+            "promote" | "forget" => {
+                arg(1)?;
+                let all = splitted[1] == "all";
+                let names = if all {
+                    Vec::new()
+                } else {
+                    splitted[1..].iter().map(|x| x.to_string()).collect()
+                };
+                if splitted[0].eq_ignore_ascii_case("promote") {
+                    Self::Promote { names, all }
+                } else {
+                    Self::Forget { names, all }
+                }
+            }
+            "stats" => {
+                let report = match arg(1)?.to_lowercase().as_ref() {
+                    "reset" => return Ok(Self::StatsReset),
+                    "top-queried" => StatsReport::TopQueried,
+                    "top-blocked" => StatsReport::TopBlocked,
+                    "top-clients" => StatsReport::TopClients,
+                    "hourly" => StatsReport::Hourly,
+                    _ => return Err(inv()),
+                };
+                let limit = match splitted.get(2) {
+                    Some(v) => Some(v.parse::<usize>().map_err(|_| inv())?),
+                    None => None,
+                };
+                Self::Stats { report, limit }
+            }
+            _ => return Err(inv()),
+        };
+        Ok(ret)
+    }
+
+    /// Returns whether the command changes the state of the server
+    pub fn is_mutating(&self) -> bool {
+        !matches!(
+            self,
+            Self::List { .. } | Self::Check { .. } | Self::Learned | Self::Stats { .. }
+        )
+    }
+}
+
+impl Display for Command {
+    /// Writes the command in the text form
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Log { level } => write!(f, "log {level}"),
+            Self::Allow { name, duration } | Self::Deny { name, duration } => {
+                let command = if matches!(self, Self::Allow { .. }) {
+                    "allow"
+                } else {
+                    "deny"
+                };
+                write!(f, "{command} {name}")?;
+                if let Some(duration) = duration {
+                    write!(f, " {}", humantime::format_duration(*duration))?;
+                }
+                Ok(())
+            }
+            Self::Remove { list, name } => write!(f, "remove {list} {name}"),
+            Self::List { list } => write!(f, "list {list}"),
+            Self::Save { list: Some(list) } => write!(f, "save {list}"),
+            Self::Save { list: None } => write!(f, "save"),
+            Self::Check { name } => write!(f, "check {name}"),
+            Self::Reload => write!(f, "reload"),
+            Self::Learned => write!(f, "learned"),
+            Self::Promote { names, all } | Self::Forget { names, all } => {
+                let command = if matches!(self, Self::Promote { .. }) {
+                    "promote"
+                } else {
+                    "forget"
+                };
+                if *all {
+                    write!(f, "{command} all")
+                } else {
+                    write!(f, "{command} {}", names.join(" "))
+                }
+            }
+            Self::Stats { report, limit } => {
+                write!(f, "stats {report}")?;
+                if let Some(limit) = limit {
+                    write!(f, " {limit}")?;
+                }
+                Ok(())
+            }
+            Self::StatsReset => write!(f, "stats reset"),
+        }
+    }
+}
+
+/// Entry of a list returned by `Command::List`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListEntry {
+    pub name: String,
+    /// Seconds until a temporary entry expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// Name (or client) and its count returned by `Command::Stats`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountEntry {
+    pub key: String,
+    pub count: u64,
+}
+
+/// Number of queries in an hour returned by `Command::Stats`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HourEntry {
+    pub hour: DateTime<Local>,
+    pub queries: u64,
+    pub blocked: u64,
+}
+
+/// Result of a command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Reply {
+    LogLevel {
+        level: String,
+    },
+    /// `added` is false if the name was already in the list
+    Added {
+        name: String,
+        list: ListKind,
+        added: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_secs: Option<u64>,
+    },
+    /// `removed` is false if the name was not in the list
+    Removed {
+        name: String,
+        list: ListKind,
+        removed: bool,
+    },
+    Saved {
+        lists: Vec<ListKind>,
+    },
+    Entries {
+        list: ListKind,
+        entries: Vec<ListEntry>,
+    },
+    Checked {
+        name: String,
+        #[serde(rename = "match")]
+        matched: Option<CheckMatch>,
+    },
+    Reloaded,
+    Learned {
+        names: Vec<LearnedName>,
+    },
+    Promoted {
+        count: usize,
+    },
+    Forgotten {
+        count: usize,
+    },
+    Counts {
+        report: StatsReport,
+        entries: Vec<CountEntry>,
+    },
+    Hourly {
+        hours: Vec<HourEntry>,
+    },
+    StatsReset,
+}
+
+impl Display for Reply {
+    /// Writes the result in the text form
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LogLevel { level } => write!(f, "Log level is changed to {level}"),
+            Self::Added {
+                name,
+                list,
+                added: true,
+                duration_secs: Some(secs),
+            } => {
+                let duration = humantime::format_duration(Duration::from_secs(*secs));
+                write!(f, "Add {name} to {} for {duration}", list.label())
+            }
+            Self::Added {
+                name,
+                list,
+                added: true,
+                ..
+            } => write!(f, "Add {name} to {}", list.label()),
+            Self::Added { name, list, .. } => write!(f, "{name} is already in {}", list.label()),
+            Self::Removed {
+                name,
+                list,
+                removed: true,
+            } => write!(f, "Remove {name} from {}", list.label()),
+            Self::Removed { name, list, .. } => write!(f, "{name} is not in {}", list.label()),
+            Self::Saved { lists } => {
+                let lines = lists
+                    .iter()
+                    .map(|x| format!("{} is saved", x.label()))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::Entries { entries, .. } => {
+                let lines = entries
+                    .iter()
+                    .map(|x| match x.expires_in_secs {
+                        Some(secs) => {
+                            let remaining = humantime::format_duration(Duration::from_secs(secs));
+                            format!("{} (expires in {remaining})", x.name)
+                        }
+                        None => x.name.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::Checked {
+                name,
+                matched: Some(v),
+            } => write!(f, "{name}: {v}"),
+            Self::Checked {
+                name,
+                matched: None,
+            } => write!(f, "{name}: NotFound"),
+            Self::Reloaded => write!(f, "Lists are reloaded"),
+            Self::Learned { names } => {
+                let lines = names
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "{} (count: {}, first: {}, last: {})",
+                            x.name,
+                            x.count,
+                            x.first_seen.format("%Y-%m-%d %H:%M:%S"),
+                            x.last_seen.format("%Y-%m-%d %H:%M:%S")
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::Promoted { count } => write!(f, "Add {count} learned FQDN(s) to AllowList"),
+            Self::Forgotten { count } => write!(f, "Forget {count} learned FQDN(s)"),
+            Self::Counts { entries, .. } => {
+                let lines = entries
+                    .iter()
+                    .map(|x| format!("{}: {}", x.key, x.count))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::Hourly { hours } => {
+                let lines = hours
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "{}: {} queries, {} blocked",
+                            x.hour.format("%Y-%m-%d %H:00"),
+                            x.queries,
+                            x.blocked
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::StatsReset => write!(f, "Statistics are reset"),
+        }
+    }
+}
+
+/// Kind of the error of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is not valid JSON or lacks fields
+    InvalidRequest,
+    UnsupportedVersion,
+    /// The token is missing or wrong
+    Unauthorized,
+    /// The command is unknown or its arguments are missing
+    InvalidCommand,
+    /// An argument cannot be parsed
+    InvalidArgument,
+    /// The command was run but failed, such as a list that could not be saved
+    Failed,
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Line of the JSON protocol sent by clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    /// Copied to the response as is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+impl Request {
+    pub fn new(command: Command, token: Option<String>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            id: None,
+            token,
+            command,
+        }
+    }
+}
+
+/// Line of the JSON protocol returned by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Reply>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CommandError>,
+}
+
+impl Response {
+    pub fn new(id: Option<serde_json::Value>, result: Result<Reply, CommandError>) -> Self {
+        let (ok, result, error) = match result {
+            Ok(v) => (true, Some(v), None),
+            Err(e) => (false, None, Some(e)),
+        };
+        Self {
+            version: PROTOCOL_VERSION,
+            id,
+            ok,
+            result,
+            error,
+        }
+    }
+
+    pub fn into_result(self) -> Result<Reply, CommandError> {
+        match (self.result, self.error) {
+            (_, Some(e)) => Err(e),
+            (Some(v), None) => Ok(v),
+            (None, None) => Err(CommandError::new(
+                ErrorCode::InvalidRequest,
+                "The response has neither a result nor an error",
+            )),
+        }
+    }
+}
+
+/// (De)serializes an optional duration as a text such as "30m"
+mod humantime_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(v: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match v {
+            Some(v) => s.serialize_str(&humantime::format_duration(*v).to_string()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(v) => humantime::parse_duration(&v)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cases = [
+            ("log debug", "log debug"),
+            ("allow www.example.com 30m", "allow www.example.com 30m"),
+            ("DENY ads.example.com", "deny ads.example.com"),
+            (
+                "remove denylist ads.example.com",
+                "remove deny ads.example.com",
+            ),
+            ("list", "list allow"),
+            ("save", "save"),
+            ("save deny", "save deny"),
+            ("explain www.example.com", "check www.example.com"),
+            ("promote all", "promote all"),
+            (
+                "forget a.example.com b.example.com",
+                "forget a.example.com b.example.com",
+            ),
+            ("stats top-blocked 20", "stats top-blocked 20"),
+            ("stats reset", "stats reset"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(expected, Command::parse(text).unwrap().to_string());
+        }
+
+        for text in [
+            "",
+            "allow",
+            "remove www.example.com",
+            "stats top",
+            "list foo",
+        ] {
+            let e = Command::parse(text).unwrap_err();
+            assert_eq!(ErrorCode::InvalidCommand, e.code, "{text}");
+        }
+        let e = Command::parse("allow www.example.com soon").unwrap_err();
+        assert_eq!(ErrorCode::InvalidArgument, e.code);
+
+        assert!(Command::parse("stats reset").unwrap().is_mutating());
+        assert!(!Command::parse("stats hourly").unwrap().is_mutating());
+    }
+
+    #[test]
+    fn test_json() {
+        let req: Request = serde_json::from_str(
+            r#"{"version":1,"id":7,"token":"secret","command":"allow","name":"www.example.com","duration":"30m"}"#,
+        )
+        .unwrap();
+        assert_eq!(Some(serde_json::json!(7)), req.id);
+        assert_eq!(Some("secret"), req.token.as_deref());
+        assert_eq!(
+            Command::Allow {
+                name: "www.example.com".into(),
+                duration: Some(Duration::from_secs(30 * 60)),
+            },
+            req.command
+        );
+
+        let req: Request = serde_json::from_str(
+            r#"{"version":1,"command":"stats","report":"top-clients","limit":5}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Command::Stats {
+                report: StatsReport::TopClients,
+                limit: Some(5)
+            },
+            req.command
+        );
+        let req: Request = serde_json::from_str(r#"{"version":1,"command":"list"}"#).unwrap();
+        assert_eq!(
+            Command::List {
+                list: ListKind::Allow
+            },
+            req.command
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"version":1,"command":"foo"}"#).is_err());
+
+        let resp = Response::new(
+            Some(serde_json::json!("a")),
+            Ok(Reply::Removed {
+                name: "www.example.com".into(),
+                list: ListKind::Deny,
+                removed: true,
+            }),
+        );
+        let text = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            r#"{"version":1,"id":"a","ok":true,"result":{"type":"removed","name":"www.example.com","list":"deny","removed":true}}"#,
+            text
+        );
+        let resp: Response = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            "Remove www.example.com from DenyList",
+            resp.into_result().unwrap().to_string()
+        );
+
+        let resp = Response::new(
+            None,
+            Err(CommandError::new(ErrorCode::Unauthorized, "Unauthorized")),
+        );
+        assert_eq!(
+            r#"{"version":1,"ok":false,"error":{"code":"unauthorized","message":"Unauthorized"}}"#,
+            serde_json::to_string(&resp).unwrap()
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::dns;
 use std::io;
+use std::path::PathBuf;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -9,8 +10,30 @@ pub enum Error {
     DNS(#[from] dns::Error),
     #[error("{0}")]
     Io(#[from] io::Error),
+    #[error("{0}")]
+    Regex(#[from] regex::Error),
+    #[error("{0}")]
+    Metrics(#[from] prometheus::Error),
+    #[error("{0}")]
+    Sqlite(#[from] rusqlite::Error),
     #[error("In-memory mode")]
     SaveButInMemory,
-    #[error("Could not delete log files")]
-    DeleteLogFiles,
+    #[error("Read-only list")]
+    SaveButReadOnly,
+    #[error("Invalid size: {0}")]
+    InvalidSize(String),
+    #[error("Invalid syslog target: {0} (expected unix:<path> or udp:<address>:<port>)")]
+    InvalidSyslogTarget(String),
+    #[error("Invalid control address: {0}")]
+    InvalidControlAddress(String),
+    #[error("Invalid response from the control server: {0}")]
+    InvalidResponse(String),
+    #[error("Invalid entry at {path}:{1}: {2}", path = .0.display())]
+    InvalidListEntry(PathBuf, usize, String),
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+    #[error("Invalid subscription name: {0}")]
+    InvalidSubscriptionName(String),
+    #[error("Failed to download {0}: {1}")]
+    Download(String, String),
 }
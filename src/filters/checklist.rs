@@ -1,8 +1,12 @@
+use super::list_file::{normalize_entry, regex_pattern, ListFile};
+use super::list_format::ListFormat;
 use crate::{Error, Result};
+use regex::RegexSet;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use wildmatch::WildMatch;
 
 #[derive(Debug)]
@@ -23,14 +27,69 @@ impl CheckList {
         })
     }
 
+    /// Imports a read-only list such as a hosts file or an AdBlock-style filter list
+    pub fn import(path: PathBuf, format: ListFormat) -> Result<Self> {
+        Ok(Self {
+            inner: InMemoryAllowList::import(path, format)?,
+        })
+    }
+
+    /// Imports `text` as the content of the file at `path`, which is written by the caller
+    pub(crate) fn import_text(path: PathBuf, text: &str, format: ListFormat) -> Result<Self> {
+        Ok(Self {
+            inner: InMemoryAllowList::import_text(path, text, format)?,
+        })
+    }
+
     pub fn check(&self, name: &str) -> bool {
         self.inner.check(name)
     }
 
+    /// Returns the entry that matches the name
+    pub fn find(&self, name: &str) -> Option<&str> {
+        self.inner.find(name)
+    }
+
+    /// Returns the line number (1-origin) of the entry in the file
+    pub fn line(&self, name: &str) -> Option<usize> {
+        self.inner.line(name)
+    }
+
+    /// Returns the entry as it is added to a list (e.g. lowercased), or `None` if it is not a valid entry
+    pub fn normalize(name: &str) -> Option<String> {
+        normalize_entry(name)
+    }
+
+    /// Returns the path of the file the list was read from
+    pub fn path(&self) -> Option<&Path> {
+        self.inner.path.as_deref()
+    }
+
     pub fn add(&mut self, name: &str) -> usize {
         self.inner.add(name)
     }
 
+    /// Adds an entry that is removed by `remove_expired` after the specified duration.
+    /// Temporary entries are not saved.
+    pub fn add_temporary(&mut self, name: &str, duration: Duration) -> usize {
+        self.inner.add_temporary(name, duration)
+    }
+
+    /// Removes the expired temporary entries and returns them
+    pub fn remove_expired(&mut self) -> Vec<String> {
+        self.inner.remove_expired()
+    }
+
+    /// Returns true if there are temporary entries to be removed
+    pub fn has_expired(&self) -> bool {
+        self.inner.has_expired()
+    }
+
+    /// Returns the remaining time of a temporary entry
+    pub fn expires_in(&self, name: &str) -> Option<Duration> {
+        self.inner.expires_in(name)
+    }
+
     pub fn delete(&mut self, name: &str) -> usize {
         self.inner.delete(name)
     }
@@ -43,6 +102,43 @@ impl CheckList {
         self.inner.save()
     }
 
+    /// Reads the file again, keeping the temporary entries.
+    /// The list is left unchanged if the file cannot be read.
+    pub fn reload(&mut self) -> Result<()> {
+        self.inner = self.inner.reloaded()?;
+        Ok(())
+    }
+
+    /// Returns the list read from the file again without modifying this one
+    pub fn reloaded(&self) -> Result<Self> {
+        Ok(Self {
+            inner: self.inner.reloaded()?,
+        })
+    }
+
+    /// Returns the file of the list, which can be read again without borrowing the list
+    pub fn source(&self) -> ListSource {
+        ListSource {
+            path: self.inner.path.clone(),
+            format: self.inner.format,
+        }
+    }
+
+    /// Replaces the entries with the list read by `ListSource::load`, keeping the temporary entries.
+    /// A list without a file is left unchanged.
+    pub fn replace(&mut self, list: CheckList) {
+        if self.inner.path.is_some() {
+            let mut inner = list.inner;
+            inner.keep_temporary(&self.inner);
+            self.inner = inner;
+        }
+    }
+
+    /// Returns the format of the list if it was imported
+    pub fn format(&self) -> Option<ListFormat> {
+        self.inner.format
+    }
+
     pub fn iter(&self) -> AllowListIterator<impl std::iter::Iterator<Item = &str>> {
         AllowListIterator {
             source: self.inner.iter(),
@@ -50,6 +146,24 @@ impl CheckList {
     }
 }
 
+/// File of a `CheckList`
+#[derive(Debug, Clone)]
+pub struct ListSource {
+    path: Option<PathBuf>,
+    format: Option<ListFormat>,
+}
+
+impl ListSource {
+    /// Reads the file. A list without a file is returned empty.
+    pub fn load(&self) -> Result<CheckList> {
+        match (self.path.as_ref(), self.format) {
+            (None, _) => Ok(CheckList::in_memory()),
+            (Some(path), Some(format)) => CheckList::import(path.clone(), format),
+            (Some(path), None) => CheckList::text(path.clone()),
+        }
+    }
+}
+
 impl Default for CheckList {
     fn default() -> Self {
         Self::in_memory()
@@ -67,62 +181,263 @@ impl<'a, I: std::iter::Iterator<Item = &'a str>> Iterator for AllowListIterator<
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
+enum Wildcard {
+    /// `*.suffix` pattern, looked up by the suffixes of the name
+    Suffix,
+    /// Any other pattern, matched one by one
+    Glob(WildMatch),
+}
+
+impl Wildcard {
+    fn new(pattern: &str) -> Self {
+        match pattern.strip_prefix("*.") {
+            Some(suffix) if !suffix.contains(['*', '?']) => Self::Suffix,
+            _ => Self::Glob(WildMatch::new(pattern)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct InMemoryAllowList {
-    #[allow(dead_code)]
     path: Option<PathBuf>,
-    names: HashMap<String, ()>,
-    wnames: HashMap<String, WildMatch>,
+    file: ListFile,
+    format: Option<ListFormat>,
+    /// Entries and their line numbers (0 for temporary entries)
+    names: HashMap<String, usize>,
+    wnames: HashMap<String, (Wildcard, usize)>,
+    /// Keys of `wnames` that have to be matched one by one
+    globs: Vec<String>,
+    /// `/regex/` entries and their line numbers
+    regexes: Vec<(String, usize)>,
+    /// `regexes` compiled into a single set
+    regex_set: RegexSet,
+    /// Expiry of the entries added by `add_temporary`
+    temporary: HashMap<String, Instant>,
 }
 
 impl InMemoryAllowList {
     pub fn new() -> Self {
         Self {
             path: None,
+            file: ListFile::new(),
+            format: None,
             names: Default::default(),
             wnames: Default::default(),
+            globs: Default::default(),
+            regexes: Default::default(),
+            regex_set: RegexSet::empty(),
+            temporary: Default::default(),
         }
     }
 
     pub fn from_file(path: PathBuf) -> Result<Self> {
-        let mut names = HashMap::new();
-        let mut wnames = HashMap::new();
-        for line in BufReader::new(File::open(&path)?).lines() {
-            let line = line?;
-            if line.contains('*') {
-                let w = WildMatch::new(&line);
-                wnames.insert(line, w);
-            } else {
-                names.insert(line, ());
-            }
+        let file = ListFile::read(&path)?;
+        let mut ret = Self::new();
+        for (line, name) in file.numbered_entries() {
+            ret.insert(name, line);
         }
+        ret.build_regex_set()?;
+        ret.path = Some(path);
+        ret.file = file;
+        Ok(ret)
+    }
 
-        Ok(Self {
-            path: Some(path),
-            names,
-            wnames,
-        })
+    pub fn import(path: PathBuf, format: ListFormat) -> Result<Self> {
+        let text = std::fs::read_to_string(&path)?;
+        Self::import_text(path, &text, format)
+    }
+
+    /// Imports `text`, which is the content of the file at `path`
+    fn import_text(path: PathBuf, text: &str, format: ListFormat) -> Result<Self> {
+        let format = if format == ListFormat::Auto {
+            ListFormat::detect(text)
+        } else {
+            format
+        };
+
+        // Public lists have hundreds of thousands of entries, so allocate the table at once
+        let lines = text.bytes().filter(|x| *x == b'\n').count();
+        let mut ret = Self::new();
+        ret.names.reserve(lines);
+        let skipped = format.parse(text, |line, name| {
+            ret.insert(name, line);
+        });
+        ret.build_regex_set()?;
+        if skipped > 0 {
+            tracing::info!(
+                "{}: {skipped} unsupported line(s) are skipped",
+                path.display()
+            );
+        }
+        ret.path = Some(path);
+        ret.format = Some(format);
+        Ok(ret)
     }
 
     pub fn check(&self, name: &str) -> bool {
-        if self.names.contains_key(name) {
-            true
+        self.find(name).is_some()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&str> {
+        if let Some((key, _)) = self.names.get_key_value(name) {
+            return Some(key);
+        }
+
+        self.find_suffix(name)
+            .or_else(|| self.find_glob(name))
+            .or_else(|| {
+                self.regex_set
+                    .matches(name)
+                    .iter()
+                    .next()
+                    .map(|i| self.regexes[i].0.as_str())
+            })
+    }
+
+    pub fn line(&self, name: &str) -> Option<usize> {
+        let line = if let Some(line) = self.names.get(name) {
+            *line
+        } else if let Some((_, line)) = self.wnames.get(name) {
+            *line
         } else {
-            self.wnames.values().any(|x| x.matches(name))
+            self.regexes.iter().find(|(x, _)| x == name)?.1
+        };
+        (line > 0).then_some(line)
+    }
+
+    /// Looks up `*.suffix` patterns for each suffix of the name (e.g. `*.example.com`, `*.com`)
+    fn find_suffix(&self, name: &str) -> Option<&str> {
+        if self.wnames.len() == self.globs.len() {
+            return None;
         }
+
+        let mut key = String::with_capacity(name.len() + 1);
+        name.match_indices('.').find_map(|(i, _)| {
+            key.clear();
+            key.push('*');
+            key.push_str(&name[i..]);
+            match self.wnames.get_key_value(key.as_str()) {
+                Some((key, (Wildcard::Suffix, _))) => Some(key.as_str()),
+                _ => None,
+            }
+        })
     }
 
+    fn find_glob(&self, name: &str) -> Option<&str> {
+        self.globs
+            .iter()
+            .find(
+                |x| matches!(self.wnames.get(*x), Some((Wildcard::Glob(w), _)) if w.matches(name)),
+            )
+            .map(String::as_str)
+    }
+
+    /// Adds the entry if it is valid, normalizing it like the entries read from the file
     pub fn add(&mut self, name: &str) -> usize {
+        let Some(name) = normalize_entry(name) else {
+            return 0;
+        };
+        let name = name.as_str();
+        if self.temporary.remove(name).is_some() {
+            // Make the temporary entry permanent
+            self.file.push(name);
+            self.set_line(name, self.file.len());
+            return 1;
+        }
+
+        let ret = self.insert_and_build(name, self.file.len() + 1);
+        if ret > 0 {
+            self.file.push(name);
+        }
+        ret
+    }
+
+    fn set_line(&mut self, name: &str, line: usize) {
+        if let Some(v) = self.names.get_mut(name) {
+            *v = line;
+        } else if let Some((_, v)) = self.wnames.get_mut(name) {
+            *v = line;
+        } else if let Some((_, v)) = self.regexes.iter_mut().find(|(x, _)| x == name) {
+            *v = line;
+        }
+    }
+
+    pub fn add_temporary(&mut self, name: &str, duration: Duration) -> usize {
+        let Some(name) = normalize_entry(name) else {
+            return 0;
+        };
+        let name = name.as_str();
+        let expiry = Instant::now() + duration;
+        if let Some(v) = self.temporary.get_mut(name) {
+            *v = expiry;
+            return 1;
+        }
+
+        let ret = self.insert_and_build(name, 0);
+        if ret > 0 {
+            self.temporary.insert(name.to_string(), expiry);
+        }
+        ret
+    }
+
+    pub fn remove_expired(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let expired = self
+            .temporary
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in expired.iter() {
+            self.temporary.remove(name);
+            self.remove(name);
+        }
+        expired
+    }
+
+    pub fn has_expired(&self) -> bool {
+        let now = Instant::now();
+        self.temporary.values().any(|x| *x <= now)
+    }
+
+    pub fn expires_in(&self, name: &str) -> Option<Duration> {
+        self.temporary
+            .get(name)
+            .map(|x| x.saturating_duration_since(Instant::now()))
+    }
+
+    fn insert_and_build(&mut self, name: &str, line: usize) -> usize {
+        let ret = self.insert(name, line);
+        if ret > 0 && regex_pattern(name).is_some() && self.build_regex_set().is_err() {
+            self.regexes.pop();
+            return 0;
+        }
+        ret
+    }
+
+    /// Registers an entry. `build_regex_set` must be called after adding `/regex/` entries.
+    fn insert(&mut self, name: &str, line: usize) -> usize {
         use std::collections::hash_map::Entry::Vacant;
-        if name.contains('*') {
+        if let Some(pattern) = regex_pattern(name) {
+            if self.regexes.iter().any(|(x, _)| x == name) || regex::Regex::new(pattern).is_err() {
+                0
+            } else {
+                self.regexes.push((name.to_string(), line));
+                1
+            }
+        } else if name.contains('*') {
             if let Vacant(e) = self.wnames.entry(name.to_string()) {
-                e.insert(WildMatch::new(name));
+                let (wildcard, _) = e.insert((Wildcard::new(name), line));
+                if matches!(wildcard, Wildcard::Glob(_)) {
+                    self.globs.push(name.to_string());
+                }
                 1
             } else {
                 0
             }
         } else if !self.names.contains_key(name) {
-            self.names.insert(name.to_string(), ());
+            self.names.insert(name.to_string(), line);
             1
         } else {
             0
@@ -130,32 +445,87 @@ impl InMemoryAllowList {
     }
 
     pub fn delete(&mut self, name: &str) -> usize {
-        if self.names.remove(name).is_some() {
+        if self.remove(name) > 0 {
+            if self.temporary.remove(name).is_none() {
+                self.file.remove(name);
+                // The following lines have moved up
+                let lines = self
+                    .file
+                    .numbered_entries()
+                    .map(|(line, x)| (line, x.to_string()))
+                    .collect::<Vec<_>>();
+                for (line, x) in lines {
+                    self.set_line(&x, line);
+                }
+            }
             1
         } else {
             0
         }
     }
 
+    fn reloaded(&self) -> Result<Self> {
+        let Some(path) = self.path.clone() else {
+            // Nothing to read
+            return Ok(self.clone());
+        };
+
+        let mut ret = if let Some(format) = self.format {
+            Self::import(path, format)?
+        } else {
+            Self::from_file(path)?
+        };
+        ret.keep_temporary(self);
+        Ok(ret)
+    }
+
+    /// Adds the temporary entries of the list that this one replaces
+    fn keep_temporary(&mut self, old: &Self) {
+        for (name, expiry) in old.temporary.iter() {
+            if self.insert_and_build(name, 0) > 0 {
+                self.temporary.insert(name.clone(), *expiry);
+            }
+        }
+    }
+
+    /// Unregisters an entry of any kind
+    fn remove(&mut self, name: &str) -> usize {
+        if regex_pattern(name).is_some() {
+            let len = self.regexes.len();
+            self.regexes.retain(|(x, _)| x != name);
+            if self.regexes.len() == len {
+                return 0;
+            }
+            // The set has been built with these patterns, so this does not fail
+            let _ = self.build_regex_set();
+            1
+        } else if self.wnames.remove(name).is_some() {
+            self.globs.retain(|x| x != name);
+            1
+        } else if self.names.remove(name).is_some() {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn build_regex_set(&mut self) -> Result<()> {
+        self.regex_set = RegexSet::new(self.regexes.iter().filter_map(|(x, _)| regex_pattern(x)))?;
+        Ok(())
+    }
+
     pub fn count(&self) -> usize {
-        self.names.len() + self.wnames.len()
+        self.names.len() + self.wnames.len() + self.regexes.len()
     }
 
     pub fn save(&self) -> Result<()> {
-        if let Some(path) = self.path.as_ref() {
-            let mut names = self.names.keys().collect::<Vec<_>>();
-            names.sort();
-            let mut wnames = self.wnames.keys().collect::<Vec<_>>();
-            wnames.sort();
+        if self.format.is_some() {
+            Err(Error::SaveButReadOnly)
+        } else if let Some(path) = self.path.as_ref() {
+            // Comments, blank lines and the order of entries are kept as they were read
             let f = File::create(path)?;
             let mut w = BufWriter::new(f);
-            for name in names {
-                writeln!(w, "{}", name)?;
-            }
-            for wname in wnames {
-                writeln!(w, "{}", wname)?;
-            }
-
+            self.file.write(&mut w)?;
             w.flush()?;
             Ok(())
         } else {
@@ -167,13 +537,15 @@ impl InMemoryAllowList {
         InMemoryAllowListIterator {
             names_keys: self.names.keys(),
             wnames_keys: self.wnames.keys(),
+            regexes: self.regexes.iter(),
         }
     }
 }
 
 pub struct InMemoryAllowListIterator<'a> {
-    names_keys: std::collections::hash_map::Keys<'a, String, ()>,
-    wnames_keys: std::collections::hash_map::Keys<'a, String, WildMatch>,
+    names_keys: std::collections::hash_map::Keys<'a, String, usize>,
+    wnames_keys: std::collections::hash_map::Keys<'a, String, (Wildcard, usize)>,
+    regexes: std::slice::Iter<'a, (String, usize)>,
 }
 
 impl<'a> Iterator for InMemoryAllowListIterator<'a> {
@@ -185,7 +557,7 @@ impl<'a> Iterator for InMemoryAllowListIterator<'a> {
         } else if let Some(key) = self.wnames_keys.next() {
             Some(key.as_str())
         } else {
-            None
+            self.regexes.next().map(|(x, _)| x.as_str())
         }
     }
 }
@@ -273,4 +645,206 @@ mod tests {
         assert!(!m.check("debian.org"));
         assert!(!m.check("www.google.co.jp"));
     }
+
+    #[test]
+    fn test_inmemory_al_suffix_and_glob() {
+        let mut m = InMemoryAllowList::new();
+        m.add("www.gnu.org");
+        m.add("*.jp");
+        m.add("www.*.org");
+        assert!(m.check("www.google.co.jp"));
+        assert!(m.check("www.gnu.org"));
+        assert!(m.check("www.kernel.org"));
+        assert!(!m.check("jp"));
+        assert!(!m.check("ftp.kernel.org"));
+
+        // The exact name is found before the patterns
+        assert_eq!(Some("www.gnu.org"), m.find("www.gnu.org"));
+        assert_eq!(Some("*.jp"), m.find("www.google.co.jp"));
+        assert_eq!(Some("www.*.org"), m.find("www.kernel.org"));
+        assert_eq!(None, m.find("ftp.kernel.org"));
+    }
+
+    #[test]
+    fn test_inmemory_al_temporary() {
+        let mut m = InMemoryAllowList::new();
+        m.add("www.example.com");
+        assert_eq!(
+            0,
+            m.add_temporary("www.example.com", Duration::from_secs(60))
+        );
+        assert_eq!(None, m.expires_in("www.example.com"));
+
+        assert_eq!(1, m.add_temporary("www.gnu.org", Duration::from_secs(60)));
+        assert_eq!(1, m.add_temporary("*.debian.org", Duration::ZERO));
+        assert_eq!(1, m.add_temporary("/^ad[0-9]+\\./", Duration::ZERO));
+        assert_eq!(4, m.count());
+        assert!(m.check("www.gnu.org"));
+        assert!(m.check("deb.debian.org"));
+        assert!(m.check("ad1.example.com"));
+        assert!(m.expires_in("www.gnu.org").unwrap() > Duration::from_secs(50));
+
+        assert!(m.has_expired());
+        let mut expired = m.remove_expired();
+        expired.sort();
+        assert_eq!(vec!["*.debian.org", "/^ad[0-9]+\\./"], expired);
+        assert!(!m.has_expired());
+        assert_eq!(2, m.count());
+        assert!(m.check("www.gnu.org"));
+        assert!(!m.check("deb.debian.org"));
+        assert!(!m.check("ad1.example.com"));
+
+        // Temporary entries are not saved until they are made permanent
+        assert_eq!(
+            vec![(1, "www.example.com")],
+            m.file.numbered_entries().collect::<Vec<_>>()
+        );
+        assert_eq!(1, m.add("www.gnu.org"));
+        assert_eq!(None, m.expires_in("www.gnu.org"));
+        assert_eq!(
+            vec![(1, "www.example.com"), (2, "www.gnu.org")],
+            m.file.numbered_entries().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(2), m.line("www.gnu.org"));
+    }
+
+    #[test]
+    fn test_inmemory_al_add_normalized() {
+        let mut m = InMemoryAllowList::new();
+        assert_eq!(1, m.add("Example.COM"));
+        assert!(m.check("example.com"));
+        assert_eq!(0, m.add("example.com"));
+        assert_eq!(1, m.add_temporary("WWW.GNU.ORG", Duration::from_secs(60)));
+        assert!(m.check("www.gnu.org"));
+
+        // Invalid entries would make the saved file unreadable
+        assert_eq!(0, m.add("bad entry"));
+        assert_eq!(0, m.add("example.org # comment"));
+        assert_eq!(0, m.add_temporary("", Duration::from_secs(60)));
+        assert_eq!(
+            vec![(1, "example.com")],
+            m.file.numbered_entries().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_inmemory_al_regex() {
+        let mut m = InMemoryAllowList::new();
+        assert_eq!(1, m.add("/^ad[0-9]+\\./"));
+        assert_eq!(0, m.add("/^ad[0-9]+\\./"));
+        assert_eq!(0, m.add("/^ad[0-9+\\./"));
+        assert_eq!(1, m.add("/^[a-z0-9]{20,}\\.com$/"));
+        assert_eq!(2, m.count());
+        assert_eq!(2, m.iter().count());
+
+        assert!(m.check("ad1.example.com"));
+        assert!(m.check("ad123.example.org"));
+        assert!(m.check("x8k2m9q0v7c5b3n1l4j6h.com"));
+        assert!(!m.check("ads.example.com"));
+        assert!(!m.check("www.ad1.example.com"));
+        assert!(!m.check("x8k2m9q0v7c5b3n1l4j6h.com.example"));
+    }
+
+    #[test]
+    fn test_inmemory_al_file() {
+        let path = std::env::temp_dir().join(format!("ldf-test-al-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# Allowed FQDNs\r\nwww.example.com  \r\n\r\n*.debian.org # mirrors\r\n",
+        )
+        .unwrap();
+
+        let mut m = InMemoryAllowList::from_file(path.clone()).unwrap();
+        assert_eq!(2, m.count());
+        assert!(m.check("www.example.com"));
+        assert!(m.check("deb.debian.org"));
+        assert!(!m.check("# Allowed FQDNs"));
+        assert!(!m.check(""));
+
+        m.add("www.gnu.org");
+        m.save().unwrap();
+        assert_eq!(
+            "# Allowed FQDNs\nwww.example.com\n\n*.debian.org # mirrors\nwww.gnu.org\n",
+            std::fs::read_to_string(&path).unwrap()
+        );
+
+        std::fs::write(&path, "www.example.com\nwww.example com\n").unwrap();
+        match InMemoryAllowList::from_file(path.clone()) {
+            Err(Error::InvalidListEntry(_, line, entry)) => {
+                assert_eq!(2, line);
+                assert_eq!("www.example com", entry);
+            }
+            v => panic!("unexpected result: {v:?}"),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_inmemory_al_reload() {
+        let path = std::env::temp_dir().join(format!("ldf-test-reload-{}.txt", std::process::id()));
+        std::fs::write(&path, "www.example.com\n*.debian.org\n/^ad[0-9]+\\./\n").unwrap();
+
+        let mut m = InMemoryAllowList::from_file(path.clone()).unwrap();
+        assert_eq!(Some("www.example.com"), m.find("www.example.com"));
+        assert_eq!(Some("*.debian.org"), m.find("deb.debian.org"));
+        assert_eq!(Some("/^ad[0-9]+\\./"), m.find("ad1.example.com"));
+        assert_eq!(None, m.find("www.gnu.org"));
+        assert_eq!(Some(2), m.line("*.debian.org"));
+        assert_eq!(Some(3), m.line("/^ad[0-9]+\\./"));
+
+        assert_eq!(1, m.delete("*.debian.org"));
+        assert_eq!(Some(2), m.line("/^ad[0-9]+\\./"));
+        m.add("www.gnu.org");
+        assert_eq!(Some(3), m.line("www.gnu.org"));
+        assert_eq!(1, m.delete("www.gnu.org"));
+        assert_eq!(1, m.delete("/^ad[0-9]+\\./"));
+        assert_eq!(0, m.delete("*.debian.org"));
+        assert!(!m.check("deb.debian.org"));
+        assert!(!m.check("ad1.example.com"));
+        m.save().unwrap();
+        assert_eq!("www.example.com\n", std::fs::read_to_string(&path).unwrap());
+
+        m.add_temporary("www.gnu.org", Duration::from_secs(60));
+        std::fs::write(&path, "www.rust-lang.org\n").unwrap();
+        let m = m.reloaded().unwrap();
+        assert!(!m.check("www.example.com"));
+        assert!(m.check("www.rust-lang.org"));
+        assert!(m.check("www.gnu.org"));
+        assert!(m.expires_in("www.gnu.org").is_some());
+
+        std::fs::write(&path, "www.example com\n").unwrap();
+        assert!(m.reloaded().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_inmemory_al_import() {
+        let path = std::env::temp_dir().join(format!("ldf-test-import-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# hosts\n127.0.0.1 localhost\n0.0.0.0 Ads.Example.com\n0.0.0.0 tracker.example.org\n",
+        )
+        .unwrap();
+
+        let m = InMemoryAllowList::import(path.clone(), ListFormat::Auto).unwrap();
+        assert_eq!(Some(ListFormat::Hosts), m.format);
+        assert_eq!(2, m.count());
+        assert!(m.check("ads.example.com"));
+        assert!(m.check("tracker.example.org"));
+        assert!(!m.check("localhost"));
+        assert_eq!(Some(3), m.line("ads.example.com"));
+        assert!(matches!(m.save(), Err(Error::SaveButReadOnly)));
+
+        std::fs::write(&path, "||ads.example.com^\n/^ad[0-9]+\\./\n").unwrap();
+        let m = InMemoryAllowList::import(path.clone(), ListFormat::Adblock).unwrap();
+        assert!(m.check("ads.example.com"));
+        assert!(m.check("www.ads.example.com"));
+        assert!(!m.check("example.com"));
+        // `/.../` rules are URL patterns and are not imported
+        assert!(!m.check("ad1.example.com"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,81 @@
+use super::CompositeCheckList;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::net::IpAddr;
+
+/// Action taken for FQDNs that are in neither the allowlist nor the denylist
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultAction {
+    /// Only FQDNs listed in the allowlist are resolved
+    #[default]
+    Deny,
+    /// FQDNs not listed in the denylist are resolved
+    Allow,
+}
+
+impl Display for DefaultAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Deny => write!(f, "deny"),
+            Self::Allow => write!(f, "allow"),
+        }
+    }
+}
+
+/// Clients identified by their source address, with their own lists
+pub struct ClientGroup {
+    pub name: String,
+    pub networks: Vec<IpNet>,
+    pub default_action: DefaultAction,
+    pub checklist: CompositeCheckList,
+}
+
+impl ClientGroup {
+    pub fn new(
+        name: impl Into<String>,
+        networks: Vec<IpNet>,
+        default_action: DefaultAction,
+        checklist: CompositeCheckList,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            networks,
+            default_action,
+            checklist,
+        }
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // IPv4 clients are seen as IPv4-mapped IPv6 addresses on a dual-stack socket
+        let addr = addr.to_canonical();
+        self.networks.iter().any(|x| x.contains(&addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let group = ClientGroup::new(
+            "kids",
+            vec![
+                "192.168.1.64/26".parse().unwrap(),
+                "fd00::/64".parse().unwrap(),
+            ],
+            DefaultAction::Deny,
+            CompositeCheckList::default(),
+        );
+
+        assert!(group.contains("192.168.1.64".parse().unwrap()));
+        assert!(group.contains("192.168.1.127".parse().unwrap()));
+        assert!(group.contains("::ffff:192.168.1.100".parse().unwrap()));
+        assert!(group.contains("fd00::1".parse().unwrap()));
+        assert!(!group.contains("192.168.1.128".parse().unwrap()));
+        assert!(!group.contains("192.168.1.1".parse().unwrap()));
+        assert!(!group.contains("fd00:0:0:1::1".parse().unwrap()));
+    }
+}
@@ -1,16 +1,123 @@
+use super::checklist::ListSource;
+use super::schedule::{Clock, LocalClock, ScheduledList};
 use super::CheckList;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CheckStatus {
     NotFound,
     Allow,
     Deny,
 }
 
-#[derive(Default)]
+/// Indicates which side a list belongs to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListKind {
+    Allow,
+    #[default]
+    Deny,
+}
+
+impl ListKind {
+    /// Name of the runtime list of the kind, such as `AllowList`
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Allow => "AllowList",
+            Self::Deny => "DenyList",
+        }
+    }
+}
+
+impl Display for ListKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// Entry that decided the result of `CompositeCheckList::check`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckMatch {
+    pub status: CheckStatus,
+    /// Name of the list that contains the entry (e.g. `denylist`, `subscription:stevenblack`)
+    pub list: String,
+    /// The entry as written in the list, such as `*.example.com`
+    pub rule: String,
+    /// File the list was read from, `None` for in-memory lists
+    pub path: Option<PathBuf>,
+    /// Line number (1-origin) of the entry, `None` if it was added at runtime
+    pub line: Option<usize>,
+}
+
+impl CheckMatch {
+    fn new(status: CheckStatus, label: impl Into<String>, list: &CheckList, rule: &str) -> Self {
+        Self {
+            status,
+            list: label.into(),
+            rule: rule.to_string(),
+            path: list.path().map(|x| x.to_path_buf()),
+            line: list.path().and_then(|_| list.line(rule)),
+        }
+    }
+}
+
+impl Display for CheckMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} by {} in {}", self.status, self.rule, self.list)?;
+        match (self.path.as_ref(), self.line) {
+            (Some(path), Some(line)) => write!(f, " ({}:{line})", path.display()),
+            (Some(path), None) => write!(f, " ({})", path.display()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// List that contains the entry found by `CompositeCheckList::find_match`
+enum MatchSource<'a> {
+    DenyList,
+    AllowList,
+    BlockList(usize),
+    Subscription(&'a str),
+    Scheduled(&'a str),
+}
+
+impl MatchSource<'_> {
+    fn label(&self) -> String {
+        match self {
+            Self::DenyList => "denylist".into(),
+            Self::AllowList => "allowlist".into(),
+            Self::BlockList(i) => format!("blocklist:{i}"),
+            Self::Subscription(key) => format!("subscription:{key}"),
+            Self::Scheduled(name) => format!("scheduled:{name}"),
+        }
+    }
+}
+
 pub struct CompositeCheckList {
     pub allowlist: CheckList,
     pub denylist: CheckList,
+    /// Read-only denylists imported from public blocklists
+    pub blocklists: Vec<CheckList>,
+    /// Allowlists downloaded from remote sources, keyed by subscription name
+    pub remote_allowlists: BTreeMap<String, CheckList>,
+    /// Denylists downloaded from remote sources, keyed by subscription name
+    pub remote_denylists: BTreeMap<String, CheckList>,
+    /// Lists that apply only during their schedules
+    pub scheduled: Vec<ScheduledList>,
+    clock: Box<dyn Clock>,
+}
+
+impl Default for CompositeCheckList {
+    fn default() -> Self {
+        Self::new(CheckList::default(), CheckList::default())
+    }
 }
 
 impl CompositeCheckList {
@@ -18,26 +125,190 @@ impl CompositeCheckList {
         Self {
             allowlist,
             denylist,
+            blocklists: Vec::new(),
+            remote_allowlists: BTreeMap::new(),
+            remote_denylists: BTreeMap::new(),
+            scheduled: Vec::new(),
+            clock: Box::new(LocalClock),
         }
     }
 
+    /// Replaces the clock used to evaluate the schedules
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Adds or replaces a list downloaded from a remote source
+    pub fn set_remote(&mut self, name: impl Into<String>, kind: ListKind, list: CheckList) {
+        match kind {
+            ListKind::Allow => self.remote_allowlists.insert(name.into(), list),
+            ListKind::Deny => self.remote_denylists.insert(name.into(), list),
+        };
+    }
+
     pub fn check(&self, name: &str) -> CheckStatus {
-        if self.denylist.check(name) {
-            // FQDN registered in the denylist is denied even if it's in the allowlist
-            CheckStatus::Deny
-        } else if self.allowlist.check(name) {
-            // FQDN not in the denylist but registered in the allowlist is allowed
-            CheckStatus::Allow
+        self.find_match(name)
+            .map_or(CheckStatus::NotFound, |(status, ..)| status)
+    }
+
+    /// Returns the entry that decides the result of `check`
+    pub fn explain(&self, name: &str) -> Option<CheckMatch> {
+        self.find_match(name)
+            .map(|(status, source, list, rule)| CheckMatch::new(status, source.label(), list, rule))
+    }
+
+    /// Finds the entry that decides the status of the FQDN, in the order of precedence
+    fn find_match(&self, name: &str) -> Option<(CheckStatus, MatchSource<'_>, &CheckList, &str)> {
+        // Get the time only when needed since this is called for each query
+        let now = if self.scheduled.is_empty() {
+            None
         } else {
-            // FQDN not in either the denylist or the allowlist is denied
-            CheckStatus::NotFound
+            Some(self.clock.now())
+        };
+        let scheduled = |kind: ListKind| {
+            let now = now?;
+            self.scheduled
+                .iter()
+                .filter(|x| x.kind == kind && x.is_active(now))
+                .find_map(|x| Some((MatchSource::Scheduled(&x.name), &x.list, x.list.find(name)?)))
+        };
+
+        // FQDN registered in the denylist is denied even if it's in the allowlist
+        let deny = self
+            .denylist
+            .find(name)
+            .map(|rule| (MatchSource::DenyList, &self.denylist, rule))
+            .or_else(|| {
+                self.blocklists
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, x)| Some((MatchSource::BlockList(i), x, x.find(name)?)))
+            })
+            .or_else(|| {
+                self.remote_denylists
+                    .iter()
+                    .find_map(|(key, x)| Some((MatchSource::Subscription(key), x, x.find(name)?)))
+            })
+            .or_else(|| scheduled(ListKind::Deny));
+        if let Some((source, list, rule)) = deny {
+            return Some((CheckStatus::Deny, source, list, rule));
+        }
+
+        // FQDN not in the denylist but registered in the allowlist is allowed
+        self.allowlist
+            .find(name)
+            .map(|rule| (MatchSource::AllowList, &self.allowlist, rule))
+            .or_else(|| {
+                self.remote_allowlists
+                    .iter()
+                    .find_map(|(key, x)| Some((MatchSource::Subscription(key), x, x.find(name)?)))
+            })
+            .or_else(|| scheduled(ListKind::Allow))
+            .map(|(source, list, rule)| (CheckStatus::Allow, source, list, rule))
+    }
+
+    /// Returns the files of the lists, which can be read again without holding the lock of this list
+    pub fn source(&self) -> CompositeSource {
+        let remote = |lists: &BTreeMap<String, CheckList>| {
+            lists.iter().map(|(k, v)| (k.clone(), v.source())).collect()
+        };
+        CompositeSource {
+            allowlist: self.allowlist.source(),
+            denylist: self.denylist.source(),
+            blocklists: self.blocklists.iter().map(|x| x.source()).collect(),
+            remote_allowlists: remote(&self.remote_allowlists),
+            remote_denylists: remote(&self.remote_denylists),
+            scheduled: self.scheduled.iter().map(|x| x.list.source()).collect(),
         }
     }
+
+    /// Replaces the lists with the ones read by `CompositeSource::load`, keeping the temporary entries.
+    /// Remote lists added in the meantime are left as they are.
+    pub fn replace(&mut self, lists: ReloadedLists) {
+        self.allowlist.replace(lists.allowlist);
+        self.denylist.replace(lists.denylist);
+        for (x, list) in self.blocklists.iter_mut().zip(lists.blocklists) {
+            x.replace(list);
+        }
+        for (key, list) in lists.remote_allowlists {
+            if let Some(x) = self.remote_allowlists.get_mut(&key) {
+                x.replace(list);
+            }
+        }
+        for (key, list) in lists.remote_denylists {
+            if let Some(x) = self.remote_denylists.get_mut(&key) {
+                x.replace(list);
+            }
+        }
+        for (x, list) in self.scheduled.iter_mut().zip(lists.scheduled) {
+            x.list.replace(list);
+        }
+    }
+
+    /// Reads all the list files again.
+    /// No list is changed if any of the files cannot be read.
+    pub fn reload(&mut self) -> crate::Result<()> {
+        let lists = self.source().load()?;
+        self.replace(lists);
+        Ok(())
+    }
+}
+
+/// Files of the lists in a `CompositeCheckList`
+#[derive(Debug, Clone)]
+pub struct CompositeSource {
+    allowlist: ListSource,
+    denylist: ListSource,
+    blocklists: Vec<ListSource>,
+    remote_allowlists: BTreeMap<String, ListSource>,
+    remote_denylists: BTreeMap<String, ListSource>,
+    scheduled: Vec<ListSource>,
+}
+
+impl CompositeSource {
+    /// Reads all the files, or fails if any of them cannot be read
+    pub fn load(&self) -> crate::Result<ReloadedLists> {
+        let load_all = |lists: &[ListSource]| {
+            lists
+                .iter()
+                .map(|x| x.load())
+                .collect::<crate::Result<Vec<_>>>()
+        };
+        let load_remote = |lists: &BTreeMap<String, ListSource>| {
+            lists
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), v.load()?)))
+                .collect::<crate::Result<BTreeMap<_, _>>>()
+        };
+        Ok(ReloadedLists {
+            allowlist: self.allowlist.load()?,
+            denylist: self.denylist.load()?,
+            blocklists: load_all(&self.blocklists)?,
+            remote_allowlists: load_remote(&self.remote_allowlists)?,
+            remote_denylists: load_remote(&self.remote_denylists)?,
+            scheduled: load_all(&self.scheduled)?,
+        })
+    }
+}
+
+/// Lists read by `CompositeSource::load`
+#[derive(Debug)]
+pub struct ReloadedLists {
+    allowlist: CheckList,
+    denylist: CheckList,
+    blocklists: Vec<CheckList>,
+    remote_allowlists: BTreeMap<String, CheckList>,
+    remote_denylists: BTreeMap<String, CheckList>,
+    scheduled: Vec<CheckList>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filters::schedule::Schedule;
+    use chrono::NaiveDateTime;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     #[test]
     fn test_check() {
@@ -48,9 +319,131 @@ mod tests {
         let mut denylist = CheckList::in_memory();
         denylist.add("example.org");
 
-        let list = CompositeCheckList::new(allowlist, denylist);
+        let mut list = CompositeCheckList::new(allowlist, denylist);
         assert_eq!(CheckStatus::Deny, list.check("example.org"));
         assert_eq!(CheckStatus::Allow, list.check("example.com"));
         assert_eq!(CheckStatus::NotFound, list.check("example.net"));
+
+        let mut blocklist = CheckList::in_memory();
+        blocklist.add("example.com");
+        list.blocklists.push(blocklist);
+        assert_eq!(CheckStatus::Deny, list.check("example.com"));
+
+        let mut remote = CheckList::in_memory();
+        remote.add("example.net");
+        list.set_remote("remote", ListKind::Allow, remote);
+        assert_eq!(CheckStatus::Allow, list.check("example.net"));
+        list.set_remote("remote", ListKind::Allow, CheckList::in_memory());
+        assert_eq!(CheckStatus::NotFound, list.check("example.net"));
+    }
+
+    #[test]
+    fn test_explain() {
+        let path =
+            std::env::temp_dir().join(format!("ldf-test-explain-{}.txt", std::process::id()));
+        std::fs::write(&path, "# ads\n\nads.example.com\n").unwrap();
+
+        let mut allowlist = CheckList::in_memory();
+        allowlist.add("*.example.com");
+        let denylist = CheckList::text(path.clone()).unwrap();
+
+        let mut list = CompositeCheckList::new(allowlist, denylist);
+        let mut remote = CheckList::in_memory();
+        remote.add("/^tracker[0-9]*\\./");
+        list.set_remote("remote", ListKind::Deny, remote);
+
+        let m = list.explain("ads.example.com").unwrap();
+        assert_eq!(CheckStatus::Deny, m.status);
+        assert_eq!("denylist", m.list);
+        assert_eq!("ads.example.com", m.rule);
+        assert_eq!(Some(path.clone()), m.path);
+        assert_eq!(Some(3), m.line);
+        assert_eq!(
+            format!("Deny by ads.example.com in denylist ({}:3)", path.display()),
+            m.to_string()
+        );
+
+        let m = list.explain("www.example.com").unwrap();
+        assert_eq!(CheckStatus::Allow, m.status);
+        assert_eq!("allowlist", m.list);
+        assert_eq!("*.example.com", m.rule);
+        assert_eq!(None, m.path);
+        assert_eq!(None, m.line);
+
+        let m = list.explain("tracker1.example.com").unwrap();
+        assert_eq!(CheckStatus::Deny, m.status);
+        assert_eq!("subscription:remote", m.list);
+        assert_eq!("/^tracker[0-9]*\\./", m.rule);
+        assert!(list.explain("example.net").is_none());
+
+        // In-memory lists have nothing to read, so they are kept as they are
+        std::fs::write(&path, "www.example.com\n").unwrap();
+        list.reload().unwrap();
+        assert_eq!(CheckStatus::Allow, list.check("ads.example.com"));
+        assert_eq!(CheckStatus::Deny, list.check("www.example.com"));
+        assert_eq!(CheckStatus::Deny, list.check("tracker1.example.com"));
+
+        // Temporary entries added while the files are read are kept
+        std::fs::write(&path, "ads.example.com\n").unwrap();
+        let lists = list.source().load().unwrap();
+        list.denylist
+            .add_temporary("temp.example.com", Duration::from_secs(60));
+        list.replace(lists);
+        assert_eq!(CheckStatus::Deny, list.check("ads.example.com"));
+        assert_eq!(CheckStatus::Deny, list.check("temp.example.com"));
+        assert_eq!(CheckStatus::Allow, list.check("www.example.com"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    struct MockClock(Arc<Mutex<NaiveDateTime>>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> NaiveDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_check_scheduled() {
+        use chrono::NaiveDate;
+
+        // 2024-01-01 is Monday
+        let monday = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let saturday = NaiveDate::from_ymd_opt(2024, 1, 6).unwrap();
+        let now = Arc::new(Mutex::new(monday.and_hms_opt(10, 0, 0).unwrap()));
+
+        let mut allowlist = CheckList::in_memory();
+        allowlist.add("social.example.com");
+        let mut list = CompositeCheckList::new(allowlist, CheckList::in_memory());
+        list.set_clock(MockClock(Arc::clone(&now)));
+
+        let mut social = CheckList::in_memory();
+        social.add("social.example.com");
+        list.scheduled.push(ScheduledList::new(
+            "social",
+            ListKind::Deny,
+            vec![Schedule::parse("mon-fri", "09:00-17:00").unwrap()],
+            social,
+        ));
+        let mut gaming = CheckList::in_memory();
+        gaming.add("*.game.example.com");
+        list.scheduled.push(ScheduledList::new(
+            "gaming",
+            ListKind::Allow,
+            vec![Schedule::parse("sat,sun", "00:00-24:00").unwrap()],
+            gaming,
+        ));
+
+        assert_eq!(CheckStatus::Deny, list.check("social.example.com"));
+        assert_eq!(CheckStatus::NotFound, list.check("www.game.example.com"));
+
+        *now.lock().unwrap() = monday.and_hms_opt(17, 0, 0).unwrap();
+        assert_eq!(CheckStatus::Allow, list.check("social.example.com"));
+        assert_eq!(CheckStatus::NotFound, list.check("www.game.example.com"));
+
+        *now.lock().unwrap() = saturday.and_hms_opt(10, 0, 0).unwrap();
+        assert_eq!(CheckStatus::Allow, list.check("social.example.com"));
+        assert_eq!(CheckStatus::Allow, list.check("www.game.example.com"));
     }
 }
@@ -0,0 +1,202 @@
+use super::checklist::CheckList;
+use super::list_file::{parse_line, Line};
+use super::list_format::ListFormat;
+use crate::Result;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+/// Problem found in a list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListIssue {
+    /// Line that cannot be read as an entry, which fails loading the list
+    Invalid { line: usize, text: String },
+    /// Entry that is already listed at the line `first`
+    Duplicate {
+        line: usize,
+        name: String,
+        first: usize,
+    },
+    /// Error of loading the list although every entry is valid (e.g. the regexes are too large as a whole)
+    Unloadable(String),
+}
+
+impl ListIssue {
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::Invalid { .. } | Self::Unloadable(_))
+    }
+}
+
+impl Display for ListIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid { line, text } => write!(f, "line {line}: Invalid entry \"{text}\""),
+            Self::Duplicate { line, name, first } => {
+                write!(f, "line {line}: {name} is already listed at line {first}")
+            }
+            Self::Unloadable(message) => write!(f, "Failed to load the list ({message})"),
+        }
+    }
+}
+
+/// Result of checking a list without loading it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListReport {
+    /// Format of an imported list (detected if `ListFormat::Auto` was given), or `None` for a text list
+    pub format: Option<ListFormat>,
+    /// Number of distinct entries
+    pub count: usize,
+    /// Number of lines of an imported list that are ignored since they are not supported
+    pub skipped: usize,
+    pub issues: Vec<ListIssue>,
+}
+
+impl ListReport {
+    /// Checks the list file. `format` is `None` for an allowlist or a denylist,
+    /// or the format of a read-only list such as a blocklist.
+    pub fn check(path: impl AsRef<Path>, format: Option<ListFormat>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let mut ret = Self::parse(&text, format);
+        if !ret.has_errors() {
+            // Loaded the same way as the server does, which also builds the regexes into a set
+            let loaded = match format {
+                Some(format) => CheckList::import(path.to_path_buf(), format),
+                None => CheckList::text(path.to_path_buf()),
+            };
+            if let Err(e) = loaded {
+                ret.issues.push(ListIssue::Unloadable(e.to_string()));
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn parse(text: &str, format: Option<ListFormat>) -> Self {
+        let mut first_lines = HashMap::new();
+        let mut issues = Vec::new();
+        let mut add = |line: usize, name: String, issues: &mut Vec<ListIssue>| {
+            match first_lines.get(&name) {
+                // A hosts file line may repeat a name, which is not worth reporting
+                Some(first) if *first == line => (),
+                Some(first) => issues.push(ListIssue::Duplicate {
+                    line,
+                    name,
+                    first: *first,
+                }),
+                None => {
+                    first_lines.insert(name, line);
+                }
+            }
+        };
+
+        let (format, skipped) = match format {
+            None => {
+                for (i, raw) in text.lines().enumerate() {
+                    match parse_line(raw) {
+                        Some(Line::Entry { name, .. }) => add(i + 1, name, &mut issues),
+                        Some(_) => (),
+                        None => issues.push(ListIssue::Invalid {
+                            line: i + 1,
+                            text: raw.trim().to_string(),
+                        }),
+                    }
+                }
+                (None, 0)
+            }
+            Some(format) => {
+                let format = if format == ListFormat::Auto {
+                    ListFormat::detect(text)
+                } else {
+                    format
+                };
+                let mut entries = Vec::new();
+                let skipped = format.parse(text, |line, name| {
+                    entries.push((line, name.to_string()));
+                });
+                for (line, name) in entries {
+                    add(line, name, &mut issues);
+                }
+                (Some(format), skipped)
+            }
+        };
+
+        Self {
+            format,
+            count: first_lines.len(),
+            skipped,
+            issues,
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(ListIssue::is_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let text =
+            "# list\nwww.example.com\nbad entry\n\nWWW.EXAMPLE.COM # again\n/[/\n*.debian.org\n";
+        let report = ListReport::parse(text, None);
+        assert_eq!(None, report.format);
+        assert_eq!(2, report.count);
+        assert_eq!(
+            vec![
+                ListIssue::Invalid {
+                    line: 3,
+                    text: "bad entry".into()
+                },
+                ListIssue::Duplicate {
+                    line: 5,
+                    name: "www.example.com".into(),
+                    first: 2
+                },
+                ListIssue::Invalid {
+                    line: 6,
+                    text: "/[/".into()
+                },
+            ],
+            report.issues
+        );
+        assert!(report.has_errors());
+        assert_eq!(
+            "line 5: www.example.com is already listed at line 2",
+            report.issues[1].to_string()
+        );
+
+        let text = "127.0.0.1 localhost\n0.0.0.0 ads.example.com ads.example.com\n0.0.0.0 x\n0.0.0.0 ADS.example.com\nfoo\n";
+        let report = ListReport::parse(text, Some(ListFormat::Auto));
+        assert_eq!(Some(ListFormat::Hosts), report.format);
+        assert_eq!(2, report.count);
+        assert_eq!(1, report.skipped);
+        assert_eq!(
+            vec![ListIssue::Duplicate {
+                line: 4,
+                name: "ads.example.com".into(),
+                first: 2
+            }],
+            report.issues
+        );
+        assert!(!report.has_errors());
+    }
+
+    #[test]
+    fn test_check_file() {
+        let path = std::env::temp_dir().join(format!("ldf-test-check-{}.txt", std::process::id()));
+        std::fs::write(&path, "www.example.com\n/^ad[0-9]+\\./\n*.debian.org\n").unwrap();
+        let report = ListReport::check(&path, None).unwrap();
+        assert_eq!(3, report.count);
+        assert!(report.issues.is_empty());
+
+        std::fs::write(&path, "0.0.0.0 ADS.example.com\n").unwrap();
+        let report = ListReport::check(&path, Some(ListFormat::Auto)).unwrap();
+        assert_eq!(Some(ListFormat::Hosts), report.format);
+        assert_eq!(1, report.count);
+        assert!(report.issues.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{Error, Result};
+use std::io::Write;
+use std::path::Path;
+
+/// A single line of a list file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    /// Empty line (or a line containing only whitespace)
+    Blank,
+    /// Line starting with `#`
+    Comment(String),
+    /// FQDN, wildcard pattern or `/regex/`, followed by the rest of the line (e.g. `  # comment`)
+    Entry { name: String, trailing: String },
+}
+
+/// Parsed representation of a list file that keeps comments, blank lines and ordering
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ListFile {
+    lines: Vec<Line>,
+}
+
+impl ListFile {
+    pub fn new() -> Self {
+        Self { lines: Vec::new() }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text)
+            .map_err(|(line, entry)| Error::InvalidListEntry(path.to_path_buf(), line, entry))
+    }
+
+    /// Parses the text of a list file.
+    /// On failure, returns the line number (1-origin) and the text of the invalid entry.
+    pub fn parse(text: &str) -> std::result::Result<Self, (usize, String)> {
+        let mut lines = Vec::new();
+        for (i, raw) in text.lines().enumerate() {
+            match parse_line(raw) {
+                Some(line) => lines.push(line),
+                None => return Err((i + 1, raw.trim().to_string())),
+            }
+        }
+
+        Ok(Self { lines })
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        for line in self.lines.iter() {
+            match line {
+                Line::Blank => writeln!(w)?,
+                Line::Comment(v) => writeln!(w, "{v}")?,
+                Line::Entry { name, trailing } => writeln!(w, "{name}{trailing}")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entries and their line numbers (1-origin)
+    pub fn numbered_entries(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.lines.iter().enumerate().filter_map(|(i, x)| match x {
+            Line::Entry { name, .. } => Some((i + 1, name.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Returns the number of lines
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Appends an entry to the end of the file
+    pub fn push(&mut self, name: &str) {
+        self.lines.push(Line::Entry {
+            name: name.to_string(),
+            trailing: String::new(),
+        });
+    }
+
+    /// Removes all lines containing the specified entry
+    pub fn remove(&mut self, name: &str) {
+        self.lines
+            .retain(|x| !matches!(x, Line::Entry { name: v, .. } if v == name));
+    }
+}
+
+/// Parses a line of a list file, or returns `None` if it is not a valid entry
+pub fn parse_line(raw: &str) -> Option<Line> {
+    let line = raw.trim();
+    if line.is_empty() {
+        return Some(Line::Blank);
+    } else if line.starts_with('#') {
+        return Some(Line::Comment(line.to_string()));
+    }
+
+    // The whitespace before `#` belongs to the trailing part
+    let name = if line.starts_with('/') {
+        // `#` may appear inside a regex, so the entry ends at the last `/`
+        line.rfind('/').map_or(line, |pos| &line[..pos + 1])
+    } else {
+        match line.find('#') {
+            Some(pos) => line[..pos].trim_end(),
+            None => line,
+        }
+    };
+    let trailing = &line[name.len()..];
+    if !is_valid_entry(name) || !(trailing.is_empty() || trailing.contains('#')) {
+        return None;
+    }
+    let name = if regex_pattern(name).is_some() {
+        name.to_string()
+    } else {
+        name.to_ascii_lowercase()
+    };
+    Some(Line::Entry {
+        name,
+        trailing: trailing.to_string(),
+    })
+}
+
+/// Returns the entry as it is stored in a list (e.g. lowercased), or `None` if it is not a valid entry
+pub fn normalize_entry(name: &str) -> Option<String> {
+    match parse_line(name)? {
+        Line::Entry { name, trailing } if trailing.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns true if the text can be used as an FQDN, a wildcard pattern or a `/regex/`
+pub fn is_valid_entry(name: &str) -> bool {
+    if let Some(pattern) = regex_pattern(name) {
+        return regex::Regex::new(pattern).is_ok();
+    }
+
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '*' | '?'))
+}
+
+/// Returns the pattern of a `/regex/` entry
+pub fn regex_pattern(name: &str) -> Option<&str> {
+    name.strip_prefix('/')?
+        .strip_suffix('/')
+        .filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# header\r\n\r\nwww.example.com  \r\n  *.debian.org # mirrors\n\nWWW.GNU.ORG\n\
+                    /^AD[0-9]+#\\./ # ads\n";
+        let list = ListFile::parse(text).unwrap();
+        assert_eq!(
+            vec![
+                Line::Comment("# header".into()),
+                Line::Blank,
+                Line::Entry {
+                    name: "www.example.com".into(),
+                    trailing: "".into()
+                },
+                Line::Entry {
+                    name: "*.debian.org".into(),
+                    trailing: " # mirrors".into()
+                },
+                Line::Blank,
+                Line::Entry {
+                    name: "www.gnu.org".into(),
+                    trailing: "".into()
+                },
+                Line::Entry {
+                    name: "/^AD[0-9]+#\\./".into(),
+                    trailing: " # ads".into()
+                },
+            ],
+            list.lines
+        );
+        assert_eq!(
+            vec![
+                (3, "www.example.com"),
+                (4, "*.debian.org"),
+                (6, "www.gnu.org"),
+                (7, "/^AD[0-9]+#\\./")
+            ],
+            list.numbered_entries().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let text = "www.example.com\n# comment\nwww example com\n";
+        assert_eq!(
+            Err((3, "www example com".to_string())),
+            ListFile::parse(text)
+        );
+        assert_eq!(
+            Err((1, "exa$mple.com".to_string())),
+            ListFile::parse("exa$mple.com")
+        );
+        assert_eq!(
+            Err((1, "/^ad[0-9+\\./".to_string())),
+            ListFile::parse("/^ad[0-9+\\./")
+        );
+        assert_eq!(Err((1, "//".to_string())), ListFile::parse("//"));
+        assert_eq!(
+            Err((1, "/ad/ foo".to_string())),
+            ListFile::parse("/ad/ foo")
+        );
+    }
+
+    #[test]
+    fn test_normalize_entry() {
+        assert_eq!(Some("example.com".into()), normalize_entry(" Example.COM "));
+        assert_eq!(Some("/^AD/".into()), normalize_entry("/^AD/"));
+        assert_eq!(None, normalize_entry("bad entry"));
+        assert_eq!(None, normalize_entry("example.com # comment"));
+        assert_eq!(None, normalize_entry("# comment"));
+        assert_eq!(None, normalize_entry(""));
+    }
+
+    #[test]
+    fn test_write() {
+        let text = "# header\n\nwww.example.com\n*.debian.org # mirrors\n";
+        let mut list = ListFile::parse(text).unwrap();
+        list.push("www.rust-lang.org");
+        list.remove("www.example.com");
+
+        let mut buf = Vec::new();
+        list.write(&mut buf).unwrap();
+        assert_eq!(
+            "# header\n\n*.debian.org # mirrors\nwww.rust-lang.org\n",
+            String::from_utf8(buf).unwrap()
+        );
+        assert_eq!(
+            vec![(3, "*.debian.org"), (4, "www.rust-lang.org")],
+            list.numbered_entries().collect::<Vec<_>>()
+        );
+        assert_eq!(4, list.len());
+    }
+}
@@ -0,0 +1,233 @@
+use super::list_file::{is_valid_entry, regex_pattern};
+use serde::Deserialize;
+use std::fmt::Display;
+use std::net::IpAddr;
+
+/// Names that appear in hosts files but must not be treated as blocked FQDNs
+const HOSTS_LOCAL_NAMES: [&str; 8] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-allnodes",
+    "ip6-allrouters",
+];
+
+/// Number of lines examined to detect the format of a list
+const DETECT_LINES: usize = 100;
+
+/// Format of an imported list
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    /// Detects the format from the content
+    #[default]
+    Auto,
+    /// One FQDN per line (e.g. `ads.example.com`)
+    Domains,
+    /// hosts file format (e.g. `0.0.0.0 ads.example.com`)
+    Hosts,
+    /// AdBlock-style rules (e.g. `||example.com^`)
+    Adblock,
+}
+
+impl ListFormat {
+    /// Detects the format of a list from its first lines
+    pub fn detect(text: &str) -> Self {
+        let mut hosts = 0;
+        let mut adblock = 0;
+        let mut domains = 0;
+        for line in text.lines().map(str::trim).take(DETECT_LINES) {
+            if line.starts_with("[Adblock") {
+                return Self::Adblock;
+            } else if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if line.starts_with('!') || line.starts_with("||") || line.starts_with("@@") {
+                adblock += 1;
+            } else if Self::parse_hosts_ip(line).is_some() {
+                hosts += 1;
+            } else {
+                domains += 1;
+            }
+        }
+
+        if adblock > 0 && adblock >= hosts && adblock >= domains {
+            Self::Adblock
+        } else if hosts > 0 && hosts >= domains {
+            Self::Hosts
+        } else {
+            Self::Domains
+        }
+    }
+
+    /// Calls `f` with the line number (1-origin) and the lowercased name for each entry found
+    /// in the text, and returns the number of skipped lines. `self` must not be `ListFormat::Auto`.
+    pub(crate) fn parse(self, text: &str, mut f: impl FnMut(usize, &str)) -> usize {
+        let mut skipped = 0;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut f = |name: &str| f(i + 1, &name.to_ascii_lowercase());
+            let ok = match self {
+                Self::Auto => unreachable!("the format must be detected before parsing"),
+                Self::Domains => Self::parse_domains(line, &mut f),
+                Self::Hosts => Self::parse_hosts(line, &mut f),
+                Self::Adblock => Self::parse_adblock(line, &mut f),
+            };
+            if !ok {
+                skipped += 1;
+            }
+        }
+        skipped
+    }
+
+    fn parse_domains(line: &str, f: &mut impl FnMut(&str)) -> bool {
+        let name = strip_comment(line);
+        if name.is_empty() {
+            true
+        } else if is_plain_name(name) {
+            f(name.trim_end_matches('.'));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_hosts(line: &str, f: &mut impl FnMut(&str)) -> bool {
+        let line = strip_comment(line);
+        if line.is_empty() {
+            return true;
+        }
+
+        let Some(names) = Self::parse_hosts_ip(line) else {
+            return false;
+        };
+        for name in names.split_whitespace() {
+            let name = name.trim_end_matches('.');
+            if HOSTS_LOCAL_NAMES.contains(&name) || name.parse::<IpAddr>().is_ok() {
+                continue;
+            }
+            if !is_plain_name(name) {
+                return false;
+            }
+            f(name);
+        }
+        true
+    }
+
+    /// Returns the names part of a hosts file line
+    fn parse_hosts_ip(line: &str) -> Option<&str> {
+        let (ip, names) = line.split_once(char::is_whitespace)?;
+        ip.parse::<IpAddr>().ok()?;
+        Some(names)
+    }
+
+    fn parse_adblock(line: &str, f: &mut impl FnMut(&str)) -> bool {
+        if line.starts_with('!') || line.starts_with('[') || line.starts_with('#') {
+            // Comments and headers
+            return true;
+        }
+
+        // Only `||example.com^` is supported. Exceptions, modifiers, cosmetic rules and
+        // URL patterns have no meaning for a DNS server. `/.../` rules are also URL patterns
+        // (e.g. `/ads/`), which would deny unrelated names such as uploads.example.com.
+        let Some(name) = line
+            .strip_prefix("||")
+            .and_then(|x| x.strip_suffix('^'))
+            .filter(|x| is_plain_name(x))
+        else {
+            return false;
+        };
+
+        // `||example.com^` blocks the domain and all of its subdomains
+        let name = name.trim_end_matches('.');
+        f(name);
+        f(&format!("*.{name}"));
+        true
+    }
+}
+
+impl Display for ListFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Domains => write!(f, "domains"),
+            Self::Hosts => write!(f, "hosts"),
+            Self::Adblock => write!(f, "adblock"),
+        }
+    }
+}
+
+/// Returns true if the text is an FQDN. Imported lists do not use the wildcards and the
+/// `/regex/` entries of the allowlist and the denylist, and a stray `*` would match everything.
+fn is_plain_name(name: &str) -> bool {
+    is_valid_entry(name) && !name.contains(['*', '?']) && regex_pattern(name).is_none()
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(pos) => line[..pos].trim_end(),
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: ListFormat, text: &str) -> (Vec<String>, usize) {
+        let mut names = Vec::new();
+        let skipped = format.parse(text, |_, x| names.push(x.to_string()));
+        (names, skipped)
+    }
+
+    #[test]
+    fn test_detect() {
+        let hosts = "# StevenBlack\n127.0.0.1 localhost\n0.0.0.0 ads.example.com\n";
+        assert_eq!(ListFormat::Hosts, ListFormat::detect(hosts));
+        let adblock = "[Adblock Plus 2.0]\n! Title: test\n||ads.example.com^\n";
+        assert_eq!(ListFormat::Adblock, ListFormat::detect(adblock));
+        let adblock = "! Title: test\n||ads.example.com^\n";
+        assert_eq!(ListFormat::Adblock, ListFormat::detect(adblock));
+        let domains = "# domains\nads.example.com\ntracker.example.org\n";
+        assert_eq!(ListFormat::Domains, ListFormat::detect(domains));
+        assert_eq!(ListFormat::Domains, ListFormat::detect(""));
+    }
+
+    #[test]
+    fn test_parse_hosts() {
+        let text =
+            "# comment\n127.0.0.1 localhost\n::1 ip6-localhost ip6-loopback\n0.0.0.0 0.0.0.0\n\
+                    0.0.0.0 ads.example.com # ads\n0.0.0.0\ta.example.org   b.example.org\n\
+                    ads.example.net\n";
+        let (names, skipped) = parse(ListFormat::Hosts, text);
+        assert_eq!(
+            vec!["ads.example.com", "a.example.org", "b.example.org"],
+            names
+        );
+        assert_eq!(1, skipped);
+    }
+
+    #[test]
+    fn test_parse_domains() {
+        let text = "# comment\nads.example.com\r\n\ntracker.example.org. # tracker\nnot a domain\n\
+                    *\n*.example.net\nad?.example.com\n/^ad/\n";
+        let (names, skipped) = parse(ListFormat::Domains, text);
+        assert_eq!(vec!["ads.example.com", "tracker.example.org"], names);
+        assert_eq!(5, skipped);
+    }
+
+    #[test]
+    fn test_parse_adblock() {
+        let text = "[Adblock Plus 2.0]\n! comment\n||ads.example.com^\n@@||good.example.com^\n\
+                    ||example.org^$third-party\nexample.net##.banner\n/^ad[0-9]+\\./\n/^ad[/\n/ads/\n||*^\n";
+        let (names, skipped) = parse(ListFormat::Adblock, text);
+        assert_eq!(vec!["ads.example.com", "*.ads.example.com"], names);
+        assert_eq!(7, skipped);
+    }
+}
@@ -1,5 +1,16 @@
 mod checklist;
+mod client_group;
 mod composite_checklist;
+mod list_check;
+mod list_file;
+mod list_format;
+mod schedule;
 
-pub use checklist::CheckList;
-pub use composite_checklist::{CheckStatus, CompositeCheckList};
+pub use checklist::{CheckList, ListSource};
+pub use client_group::{ClientGroup, DefaultAction};
+pub use composite_checklist::{
+    CheckMatch, CheckStatus, CompositeCheckList, CompositeSource, ListKind, ReloadedLists,
+};
+pub use list_check::{ListIssue, ListReport};
+pub use list_format::ListFormat;
+pub use schedule::{Clock, LocalClock, Schedule, ScheduledList};
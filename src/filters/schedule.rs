@@ -0,0 +1,192 @@
+use super::{CheckList, ListKind};
+use crate::{Error, Result};
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use std::fmt::Display;
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Source of the current local time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// Clock that returns the local time of the system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> NaiveDateTime {
+        chrono::Local::now().naive_local()
+    }
+}
+
+/// Days of the week and a time range (e.g. `mon-fri` and `09:00-17:00`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    /// Bit flags of the days, the least significant bit is Monday
+    days: u8,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Schedule {
+    /// Parses days such as `mon-fri`, `sat,sun` or `*` and hours such as `09:00-17:00`.
+    /// The end time is exclusive, and a range such as `22:00-06:00` spans midnight.
+    /// The part after midnight belongs to the day the range starts.
+    pub fn parse(days: &str, hours: &str) -> Result<Self> {
+        let inv = || Error::InvalidSchedule(format!("{days} {hours}"));
+
+        let mut bits = 0;
+        for item in days.split(',').map(str::trim) {
+            if item == "*" {
+                bits = 0x7F;
+                continue;
+            }
+            let (first, last) = item.split_once('-').unwrap_or((item, item));
+            let first = parse_weekday(first).ok_or_else(inv)?;
+            let last = parse_weekday(last).ok_or_else(inv)?;
+            let mut day = first;
+            loop {
+                bits |= 1 << day.num_days_from_monday();
+                if day == last {
+                    break;
+                }
+                day = day.succ();
+            }
+        }
+
+        let (start, end) = hours.split_once('-').ok_or_else(inv)?;
+        let start = parse_time(start).ok_or_else(inv)?;
+        let end = parse_time(end).ok_or_else(inv)?;
+        Ok(Self {
+            days: bits,
+            start,
+            end,
+        })
+    }
+
+    pub fn contains(&self, t: NaiveDateTime) -> bool {
+        let time = t.time();
+        let (day, active) = if self.start <= self.end {
+            (t.weekday(), self.start <= time && time < self.end)
+        } else if time < self.end {
+            // After midnight, the range started on the previous day
+            (t.weekday().pred(), true)
+        } else {
+            (t.weekday(), self.start <= time)
+        };
+        active && self.days & (1 << day.num_days_from_monday()) != 0
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let days = WEEKDAYS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.days & (1 << i) != 0)
+            .map(|(_, x)| *x)
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{} {}-{}",
+            days.join(","),
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+fn parse_weekday(v: &str) -> Option<Weekday> {
+    v.trim().parse::<Weekday>().ok()
+}
+
+fn parse_time(v: &str) -> Option<NaiveTime> {
+    let v = v.trim();
+    if v == "24:00" {
+        // Treat as the end of the day
+        NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999)
+    } else {
+        NaiveTime::parse_from_str(v, "%H:%M").ok()
+    }
+}
+
+/// List that applies only while one of its schedules is active
+#[derive(Debug)]
+pub struct ScheduledList {
+    pub name: String,
+    pub kind: ListKind,
+    pub schedules: Vec<Schedule>,
+    pub list: CheckList,
+}
+
+impl ScheduledList {
+    pub fn new(
+        name: impl Into<String>,
+        kind: ListKind,
+        schedules: Vec<Schedule>,
+        list: CheckList,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            schedules,
+            list,
+        }
+    }
+
+    pub fn is_active(&self, t: NaiveDateTime) -> bool {
+        self.schedules.iter().any(|x| x.contains(t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        // 2024-01-01 is Monday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schedule() {
+        let s = Schedule::parse("mon-fri", "09:00-17:00").unwrap();
+        assert_eq!("mon,tue,wed,thu,fri 09:00-17:00", s.to_string());
+        assert!(s.contains(at(1, 9, 0)));
+        assert!(s.contains(at(5, 16, 59)));
+        assert!(!s.contains(at(1, 17, 0)));
+        assert!(!s.contains(at(1, 8, 59)));
+        assert!(!s.contains(at(6, 12, 0)));
+
+        let s = Schedule::parse("Sat, Sun", "00:00-24:00").unwrap();
+        assert!(s.contains(at(6, 0, 0)));
+        assert!(s.contains(at(7, 23, 59)));
+        assert!(!s.contains(at(5, 23, 59)));
+
+        let s = Schedule::parse("fri-mon", "22:00-06:00").unwrap();
+        assert_eq!("mon,fri,sat,sun 22:00-06:00", s.to_string());
+        assert!(s.contains(at(5, 23, 0)));
+        assert!(s.contains(at(1, 5, 59)));
+        assert!(!s.contains(at(1, 6, 0)));
+        assert!(!s.contains(at(3, 23, 0)));
+
+        let s = Schedule::parse("mon-fri", "22:00-06:00").unwrap();
+        assert!(s.contains(at(1, 22, 0)));
+        assert!(s.contains(at(2, 3, 0)));
+        assert!(!s.contains(at(1, 3, 0)));
+        assert!(s.contains(at(6, 3, 0)));
+        assert!(!s.contains(at(6, 22, 0)));
+
+        let s = Schedule::parse("*", "12:00-13:00").unwrap();
+        assert!(s.contains(at(3, 12, 30)));
+
+        assert!(Schedule::parse("mon-fri", "09:00").is_err());
+        assert!(Schedule::parse("weekday", "09:00-17:00").is_err());
+        assert!(Schedule::parse("mon", "9-17").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use crate::query_context::QueryContext;
+use crate::resolve_event::ResolveEvent;
+use crate::resolved_status::ResolvedStatus;
+use crate::Result;
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::{params, Connection, OpenFlags};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of records written at once
+const BATCH_SIZE: usize = 100;
+/// Maximum time a record waits to be written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval between the removals of old records
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A query recorded in the history
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    pub timestamp: DateTime<Local>,
+    pub client: String,
+    pub qname: String,
+    pub qtype: String,
+    pub decision: String,
+    pub rcode: String,
+    /// Entry that denied the query
+    pub rule: Option<String>,
+    pub upstream: Option<String>,
+    pub latency_ms: f64,
+    /// Answers such as `A(192.0.2.1) CNAME(www.example.com)`
+    pub answers: String,
+}
+
+impl HistoryRecord {
+    pub fn new(ctx: &QueryContext, status: &ResolvedStatus) -> Self {
+        let rule = match status {
+            ResolvedStatus::Deny(_, _, Some(m)) => Some(format!("{} in {}", m.rule, m.list)),
+            _ => None,
+        };
+        let answers = status
+            .data()
+            .resp
+            .iter()
+            .flat_map(|(k, v)| v.iter().map(move |x| format!("{k}({x})")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            timestamp: Local::now(),
+            client: ctx.client.ip().to_canonical().to_string(),
+            qname: ctx.qname.clone(),
+            qtype: ctx.qtype.to_string(),
+            decision: status.decision().into(),
+            rcode: format!("{:?}", status.rescode()),
+            rule,
+            upstream: ctx.upstream.map(|x| x.to_string()),
+            latency_ms: ctx.elapsed.as_secs_f64() * 1000.0,
+            answers,
+        }
+    }
+}
+
+/// Conditions to search the history
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    pub client: Option<String>,
+    /// Part of the name
+    pub name: Option<String>,
+    pub decision: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Query history stored in a SQLite database
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS queries (
+                id INTEGER PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                client TEXT NOT NULL,
+                qname TEXT NOT NULL,
+                qtype TEXT NOT NULL,
+                decision TEXT NOT NULL,
+                rcode TEXT NOT NULL,
+                rule TEXT,
+                upstream TEXT,
+                latency_ms REAL NOT NULL,
+                answers TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS queries_timestamp ON queries (timestamp);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Ok(Self { conn })
+    }
+
+    pub fn insert(&mut self, records: &[HistoryRecord]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO queries (timestamp, client, qname, qtype, decision, rcode, rule, \
+                 upstream, latency_ms, answers) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for v in records.iter() {
+                stmt.execute(params![
+                    v.timestamp.timestamp_millis(),
+                    v.client,
+                    v.qname,
+                    v.qtype,
+                    v.decision,
+                    v.rcode,
+                    v.rule,
+                    v.upstream,
+                    v.latency_ms,
+                    v.answers,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes the records older than the threshold and returns the number of them
+    pub fn remove_older_than(&self, threshold: DateTime<Local>) -> Result<usize> {
+        let ret = self.conn.execute(
+            "DELETE FROM queries WHERE timestamp < ?1",
+            params![threshold.timestamp_millis()],
+        )?;
+        Ok(ret)
+    }
+
+    /// Returns the newest records matching the filter up to the limit, oldest first
+    pub fn search(&self, filter: &HistoryFilter) -> Result<Vec<HistoryRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT timestamp, client, qname, qtype, decision, rcode, rule, upstream, \
+             latency_ms, answers FROM queries
+             WHERE (?1 IS NULL OR timestamp >= ?1)
+               AND (?2 IS NULL OR timestamp < ?2)
+               AND (?3 IS NULL OR client = ?3)
+               AND (?4 IS NULL OR instr(qname, ?4) > 0)
+               AND (?5 IS NULL OR decision = ?5)
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?6",
+        )?;
+        let limit = filter.limit.map_or(-1, |x| x as i64);
+        let rows = stmt.query_map(
+            params![
+                filter.from.map(|x| x.timestamp_millis()),
+                filter.to.map(|x| x.timestamp_millis()),
+                filter.client,
+                filter.name,
+                filter.decision,
+                limit,
+            ],
+            |row| {
+                let timestamp: i64 = row.get(0)?;
+                Ok(HistoryRecord {
+                    timestamp: Local
+                        .timestamp_millis_opt(timestamp)
+                        .single()
+                        .unwrap_or_default(),
+                    client: row.get(1)?,
+                    qname: row.get(2)?,
+                    qtype: row.get(3)?,
+                    decision: row.get(4)?,
+                    rcode: row.get(5)?,
+                    rule: row.get(6)?,
+                    upstream: row.get(7)?,
+                    latency_ms: row.get(8)?,
+                    answers: row.get(9)?,
+                })
+            },
+        )?;
+        let mut ret = Vec::new();
+        for row in rows {
+            ret.push(row?);
+        }
+        // Shown in chronological order
+        ret.reverse();
+        Ok(ret)
+    }
+}
+
+/// Writes the queries into the history in batches on a background thread
+pub struct HistoryResolveEvent {
+    tx: Mutex<mpsc::Sender<HistoryRecord>>,
+}
+
+impl HistoryResolveEvent {
+    /// Opens the history and starts the writer thread.
+    /// Records older than `retention` are removed periodically.
+    pub fn spawn(path: impl AsRef<Path>, retention: Duration) -> Result<Self> {
+        let history = History::open(path)?;
+        let (tx, rx) = mpsc::channel::<HistoryRecord>();
+        std::thread::spawn(move || {
+            let mut history = history;
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            let mut last_flush = Instant::now();
+            let mut last_cleanup: Option<Instant> = None;
+            loop {
+                let disconnected = match rx.recv_timeout(FLUSH_INTERVAL) {
+                    Ok(record) => {
+                        batch.push(record);
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+
+                if !batch.is_empty()
+                    && (disconnected
+                        || batch.len() >= BATCH_SIZE
+                        || last_flush.elapsed() >= FLUSH_INTERVAL)
+                {
+                    if let Err(e) = history.insert(&batch) {
+                        tracing::error!("Failed to write {} query history ({e})", batch.len());
+                    }
+                    batch.clear();
+                    last_flush = Instant::now();
+                }
+
+                if last_cleanup.is_none_or(|x| x.elapsed() >= CLEANUP_INTERVAL) {
+                    last_cleanup = Some(Instant::now());
+                    let threshold = Local::now() - retention;
+                    match history.remove_older_than(threshold) {
+                        Ok(0) => (),
+                        Ok(n) => tracing::info!("Deleted {n} query history older than {threshold}"),
+                        Err(e) => tracing::error!("Failed to delete old query history ({e})"),
+                    }
+                }
+
+                if disconnected {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { tx: Mutex::new(tx) })
+    }
+
+    pub fn write(&self, ctx: &QueryContext, status: &ResolvedStatus) {
+        let record = HistoryRecord::new(ctx, status);
+        if let Ok(tx) = self.tx.lock() {
+            let _ = tx.send(record);
+        }
+    }
+}
+
+impl ResolveEvent for HistoryResolveEvent {
+    fn resolving(&self, _ctx: &QueryContext) {}
+
+    fn resolved(&self, ctx: &QueryContext, status: ResolvedStatus) {
+        self.write(ctx, &status);
+    }
+
+    fn error(&self, message: impl AsRef<str>) {
+        tracing::error!("{}", message.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hour: u32, client: &str, qname: &str, decision: &str) -> HistoryRecord {
+        HistoryRecord {
+            timestamp: Local.with_ymd_and_hms(2024, 1, 1, hour, 30, 0).unwrap(),
+            client: client.into(),
+            qname: qname.into(),
+            qtype: "A".into(),
+            decision: decision.into(),
+            rcode: "NoError".into(),
+            rule: None,
+            upstream: Some("8.8.8.8".into()),
+            latency_ms: 1.5,
+            answers: "A(192.0.2.1)".into(),
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let path = std::env::temp_dir().join(format!("ldf-test-history-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::open(&path).unwrap();
+        let records = vec![
+            record(13, "192.168.1.10", "www.example.com", "allow"),
+            record(14, "192.168.1.10", "www.gnu.org", "allow"),
+            record(14, "192.168.1.11", "ads.example.com", "deny"),
+            record(15, "192.168.1.10", "www.example.com", "allow"),
+        ];
+        history.insert(&records).unwrap();
+
+        let ret = history.search(&HistoryFilter::default()).unwrap();
+        assert_eq!(records, ret);
+
+        let filter = HistoryFilter {
+            from: Some(Local.with_ymd_and_hms(2024, 1, 1, 14, 0, 0).unwrap()),
+            to: Some(Local.with_ymd_and_hms(2024, 1, 1, 15, 0, 0).unwrap()),
+            client: Some("192.168.1.10".into()),
+            ..Default::default()
+        };
+        let ret = history.search(&filter).unwrap();
+        assert_eq!(vec![records[1].clone()], ret);
+
+        let filter = HistoryFilter {
+            name: Some("example".into()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let ret = history.search(&filter).unwrap();
+        // The newest records are returned
+        assert_eq!(vec![records[2].clone(), records[3].clone()], ret);
+
+        let filter = HistoryFilter {
+            decision: Some("deny".into()),
+            ..Default::default()
+        };
+        assert_eq!(1, history.search(&filter).unwrap().len());
+
+        let threshold = Local.with_ymd_and_hms(2024, 1, 1, 14, 0, 0).unwrap();
+        assert_eq!(1, history.remove_older_than(threshold).unwrap());
+        assert_eq!(3, history.search(&HistoryFilter::default()).unwrap().len());
+
+        drop(history);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
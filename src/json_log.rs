@@ -0,0 +1,156 @@
+use crate::query_context::QueryContext;
+use crate::resolve_event::ResolveEvent;
+use crate::resolved_status::ResolvedStatus;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+
+/// A line of the JSON query log
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    client: IpAddr,
+    id: u16,
+    qname: &'a str,
+    qtype: String,
+    decision: &'static str,
+    /// Entry that allowed or denied the query
+    rule: Option<&'a str>,
+    list: Option<&'a str>,
+    upstream: Option<Ipv4Addr>,
+    latency_ms: f64,
+    rcode: String,
+    answers: BTreeMap<String, &'a [String]>,
+}
+
+/// Writes one JSON object per query
+pub struct JsonResolveEvent<W: Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonResolveEvent<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn write(&self, ctx: &QueryContext, status: &ResolvedStatus) {
+        let data = status.data();
+        let m = status.matched();
+        let record = Record {
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            client: ctx.client.ip(),
+            id: ctx.id,
+            qname: &data.req_name,
+            qtype: data.req_qtype.to_string(),
+            decision: status.decision(),
+            rule: m.map(|x| x.rule.as_str()),
+            list: m.map(|x| x.list.as_str()),
+            upstream: ctx.upstream,
+            latency_ms: ctx.elapsed.as_secs_f64() * 1000.0,
+            rcode: format!("{:?}", status.rescode()),
+            answers: data
+                .resp
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.as_slice()))
+                .collect(),
+        };
+
+        let ret = match self.writer.lock() {
+            Ok(mut w) => serde_json::to_writer(&mut *w, &record)
+                .map_err(std::io::Error::from)
+                .and_then(|_| writeln!(w)),
+            Err(_) => Err(std::io::Error::other("lock error")),
+        };
+        if let Err(e) = ret {
+            tracing::error!("Failed to write JSON query log ({e})");
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write> ResolveEvent for JsonResolveEvent<W> {
+    fn resolving(&self, _ctx: &QueryContext) {}
+
+    fn resolved(&self, ctx: &QueryContext, status: ResolvedStatus) {
+        self.write(ctx, &status)
+    }
+
+    fn error(&self, message: impl AsRef<str>) {
+        tracing::error!("{}", message.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{QueryType, ResultCode};
+    use crate::filters::{CheckMatch, CheckStatus};
+    use crate::resolved_data::ResolvedData;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_write() {
+        let event = JsonResolveEvent::new(Vec::new());
+
+        let client = "192.168.1.10:50000".parse().unwrap();
+        let mut ctx = QueryContext::new(1, client, QueryType::A, "www.example.com", Instant::now());
+        ctx.upstream = Some(Ipv4Addr::new(8, 8, 8, 8));
+        ctx.elapsed = Duration::from_micros(1500);
+        let mut data = ResolvedData::new(QueryType::A, "www.example.com");
+        data.insert(QueryType::A, "192.0.2.1");
+        let m = CheckMatch {
+            status: CheckStatus::Allow,
+            list: "allowlist".into(),
+            rule: "*.example.com".into(),
+            path: None,
+            line: None,
+        };
+        event.resolved(&ctx, ResolvedStatus::Allow(data, Some(m)));
+
+        let ctx = QueryContext::new(
+            2,
+            client,
+            QueryType::AAAA,
+            "ads.example.com",
+            Instant::now(),
+        );
+        event.resolved(
+            &ctx,
+            ResolvedStatus::Deny(
+                ResolvedData::new(QueryType::AAAA, "ads.example.com"),
+                ResultCode::NXDomain,
+                None,
+            ),
+        );
+
+        let text = String::from_utf8(event.into_inner()).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(2, lines.len());
+
+        let v: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!("192.168.1.10", v["client"]);
+        assert_eq!(1, v["id"]);
+        assert_eq!("www.example.com", v["qname"]);
+        assert_eq!("A", v["qtype"]);
+        assert_eq!("allow", v["decision"]);
+        assert_eq!("8.8.8.8", v["upstream"]);
+        assert_eq!(1.5, v["latency_ms"]);
+        assert_eq!("NoError", v["rcode"]);
+        assert_eq!("192.0.2.1", v["answers"]["A"][0]);
+        assert_eq!("*.example.com", v["rule"]);
+        assert_eq!("allowlist", v["list"]);
+
+        let v: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!("deny", v["decision"]);
+        assert_eq!("NXDomain", v["rcode"]);
+        assert!(v["upstream"].is_null());
+        assert!(v["rule"].is_null());
+    }
+}
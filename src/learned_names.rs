@@ -0,0 +1,105 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Maximum number of names to be recorded to keep the memory bounded
+const MAX_LEARNED_NAMES: usize = 10_000;
+
+/// FQDN that was resolved in learning mode although it is not listed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LearnedName {
+    pub name: String,
+    pub count: usize,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+}
+
+/// Records of unlisted FQDNs, waiting for approval
+#[derive(Debug, Default)]
+pub struct LearnedNames {
+    names: HashMap<String, LearnedName>,
+}
+
+impl LearnedNames {
+    pub fn new() -> Self {
+        Self {
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, name: &str) {
+        self.record_at(name, Local::now());
+    }
+
+    fn record_at(&mut self, name: &str, now: DateTime<Local>) {
+        if let Some(v) = self.names.get_mut(name) {
+            v.count = v.count.saturating_add(1);
+            v.last_seen = now;
+        } else if self.names.len() < MAX_LEARNED_NAMES {
+            self.names.insert(
+                name.to_string(),
+                LearnedName {
+                    name: name.to_string(),
+                    count: 1,
+                    first_seen: now,
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Returns the records in descending order of count
+    pub fn list(&self) -> Vec<&LearnedName> {
+        let mut ret = self.names.values().collect::<Vec<_>>();
+        ret.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        ret
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<LearnedName> {
+        self.names.remove(name)
+    }
+
+    /// Removes all the records and returns them in descending order of count
+    pub fn take_all(&mut self) -> Vec<LearnedName> {
+        let mut ret = self.names.drain().map(|(_, v)| v).collect::<Vec<_>>();
+        ret.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        ret
+    }
+
+    pub fn count(&self) -> usize {
+        self.names.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_record() {
+        let t1 = Local.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        let t2 = Local.with_ymd_and_hms(2024, 1, 1, 11, 0, 0).unwrap();
+        let mut m = LearnedNames::new();
+        m.record_at("www.example.com", t1);
+        m.record_at("www.gnu.org", t1);
+        m.record_at("www.gnu.org", t2);
+
+        let list = m.list();
+        assert_eq!(2, list.len());
+        assert_eq!("www.gnu.org", list[0].name);
+        assert_eq!(2, list[0].count);
+        assert_eq!(t1, list[0].first_seen);
+        assert_eq!(t2, list[0].last_seen);
+        assert_eq!("www.example.com", list[1].name);
+        assert_eq!(1, list[1].count);
+
+        assert_eq!(1, m.remove("www.example.com").unwrap().count);
+        assert!(m.remove("www.example.com").is_none());
+        assert_eq!(1, m.count());
+
+        let all = m.take_all();
+        assert_eq!(1, all.len());
+        assert_eq!(0, m.count());
+    }
+}
@@ -1,18 +1,41 @@
+pub mod control;
 pub mod dns;
 pub mod error;
 mod filters;
+pub mod history;
+mod json_log;
+mod learned_names;
+mod log_suppressor;
 pub mod logger;
+pub mod metrics;
+mod query_context;
 mod resolve_event;
 mod resolved_data;
 mod resolved_status;
 pub mod server;
+mod stats;
+pub mod subscription;
+mod syslog_layer;
 
 pub use error::{Error, Result};
-pub use filters::{CheckList, CompositeCheckList};
+pub use filters::{
+    CheckList, CheckMatch, CheckStatus, ClientGroup, CompositeCheckList, DefaultAction,
+};
+pub use filters::{Clock, ListFormat, ListKind, LocalClock, Schedule, ScheduledList};
+pub use filters::{CompositeSource, ListIssue, ListReport, ListSource, ReloadedLists};
+pub use history::{History, HistoryFilter, HistoryRecord, HistoryResolveEvent};
+pub use json_log::JsonResolveEvent;
+pub use learned_names::{LearnedName, LearnedNames};
+pub use log_suppressor::LogSuppressor;
+pub use metrics::Metrics;
+pub use query_context::QueryContext;
 pub use resolve_event::{DefaultResolveEvent, ResolveEvent, TracingResolveEvent};
 pub use resolved_data::ResolvedData;
 pub use resolved_status::ResolvedStatus;
 pub use server::{Config, Server, ServerConfigBuilder};
+pub use stats::{HourlyCount, Stats};
+pub use subscription::Subscription;
+pub use syslog_layer::SyslogTarget;
 
 pub fn get_version() -> String {
     let version = env!("CARGO_PKG_VERSION");
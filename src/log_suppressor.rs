@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Maximum number of keys to be tracked to keep the memory bounded
+const MAX_KEYS: usize = 10_000;
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: usize,
+}
+
+impl Window {
+    fn suppressed(&self, threshold: usize) -> usize {
+        self.count.saturating_sub(threshold)
+    }
+}
+
+/// Limits the number of logs per key within a time window
+#[derive(Debug)]
+pub struct LogSuppressor {
+    threshold: usize,
+    window: Duration,
+    windows: HashMap<String, Window>,
+    /// Keys removed before their windows were summarized and the number of suppressed logs
+    pending: Vec<(String, usize)>,
+}
+
+impl LogSuppressor {
+    /// Allows `threshold` logs per key in each `window`.
+    /// A threshold of 0 disables the suppression.
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            windows: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Counts a log of the key and returns whether it should be output
+    pub fn check(&mut self, key: &str) -> bool {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&mut self, key: &str, now: Instant) -> bool {
+        if self.threshold == 0 {
+            return true;
+        }
+
+        if let Some(v) = self.windows.get_mut(key) {
+            if now.duration_since(v.started) >= self.window {
+                let suppressed = v.suppressed(self.threshold);
+                if suppressed > 0 {
+                    self.pending.push((key.to_string(), suppressed));
+                }
+                v.started = now;
+                v.count = 0;
+            }
+            v.count = v.count.saturating_add(1);
+            return v.count <= self.threshold;
+        }
+
+        if self.windows.len() >= MAX_KEYS {
+            self.evict_expired(now);
+        }
+        if self.windows.len() >= MAX_KEYS {
+            self.evict_oldest();
+        }
+        self.windows.insert(
+            key.to_string(),
+            Window {
+                started: now,
+                count: 1,
+            },
+        );
+        true
+    }
+
+    /// Removes the keys whose windows have ended and returns the number of logs
+    /// suppressed in them, including those of the keys evicted since the last call
+    pub fn flush(&mut self) -> Vec<(String, usize)> {
+        self.flush_at(Instant::now())
+    }
+
+    fn flush_at(&mut self, now: Instant) -> Vec<(String, usize)> {
+        self.evict_expired(now);
+        let mut ret = std::mem::take(&mut self.pending);
+        ret.sort();
+        ret
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let threshold = self.threshold;
+        let window = self.window;
+        let pending = &mut self.pending;
+        self.windows.retain(|k, v| {
+            if now.duration_since(v.started) < window {
+                return true;
+            }
+            let suppressed = v.suppressed(threshold);
+            if suppressed > 0 {
+                pending.push((k.clone(), suppressed));
+            }
+            false
+        });
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .windows
+            .iter()
+            .min_by_key(|(_, v)| v.started)
+            .map(|(k, _)| k.clone());
+        if let Some(key) = oldest {
+            if let Some(v) = self.windows.remove(&key) {
+                let suppressed = v.suppressed(self.threshold);
+                if suppressed > 0 {
+                    self.pending.push((key, suppressed));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let t0 = Instant::now();
+        let mut m = LogSuppressor::new(2, Duration::from_secs(60));
+        assert!(m.check_at("<A> www.example.com", t0));
+        assert!(m.check_at("<A> www.example.com", t0));
+        assert!(!m.check_at("<A> www.example.com", t0));
+        assert!(!m.check_at("<A> www.example.com", t0 + Duration::from_secs(30)));
+        assert!(m.check_at("<AAAA> www.example.com", t0));
+        assert!(m.flush_at(t0 + Duration::from_secs(30)).is_empty());
+
+        // A new window starts after the window has ended
+        assert!(m.check_at("<A> www.example.com", t0 + Duration::from_secs(60)));
+        assert!(m.check_at("<A> www.example.com", t0 + Duration::from_secs(61)));
+        assert!(!m.check_at("<A> www.example.com", t0 + Duration::from_secs(62)));
+        assert_eq!(
+            vec![("<A> www.example.com".to_string(), 2)],
+            m.flush_at(t0 + Duration::from_secs(90))
+        );
+
+        let ret = m.flush_at(t0 + Duration::from_secs(120));
+        assert_eq!(vec![("<A> www.example.com".to_string(), 1)], ret);
+        assert!(m.windows.is_empty());
+
+        let mut m = LogSuppressor::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            assert!(m.check_at("<A> www.example.com", t0));
+        }
+    }
+
+    #[test]
+    fn test_bounded() {
+        let t0 = Instant::now();
+        let mut m = LogSuppressor::new(1, Duration::from_secs(60));
+        m.check_at("oldest", t0);
+        m.check_at("oldest", t0);
+        for i in 1..MAX_KEYS {
+            m.check_at(&format!("{i}"), t0 + Duration::from_secs(1));
+        }
+        assert_eq!(MAX_KEYS, m.windows.len());
+
+        m.check_at("newest", t0 + Duration::from_secs(2));
+        assert_eq!(MAX_KEYS, m.windows.len());
+        assert!(!m.windows.contains_key("oldest"));
+        assert_eq!(
+            vec![("oldest".to_string(), 1)],
+            m.flush_at(t0 + Duration::from_secs(2))
+        );
+    }
+}
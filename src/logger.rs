@@ -1,117 +1,559 @@
 use crate::error::{Error, Result};
+use crate::syslog_layer::{SyslogLayer, SyslogTarget};
+use serde::Deserialize;
 use std::fs;
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::level_filters::LevelFilter;
 use tracing_appender::non_blocking;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::FilterExt;
 use tracing_subscriber::{filter, fmt, reload};
-use tracing_subscriber::{prelude::*, Registry};
+use tracing_subscriber::{prelude::*, Layer, Registry};
 
+const APP_NAME: &str = "ldf";
 const LOGFILE_PREFIX: &str = "local-dns-forwarder.log";
+const JSON_LOGFILE_PREFIX: &str = "local-dns-forwarder.json";
+const QUERY_LOGFILE_PREFIX: &str = "local-dns-forwarder.query";
+
+/// Target of the events that record queries.
+/// They are written to the query log instead of the diagnostic log.
+pub const QUERY_TARGET: &str = "ldf::query";
+
+/// Target of the events that record the changes made through the control server.
+/// They are written to the diagnostic log regardless of the log level.
+pub const AUDIT_TARGET: &str = "ldf::audit";
+
+/// Interval between the checks of the log files
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 pub type ReloadHandle = reload::Handle<LevelFilter, Registry>;
 
+/// Interval at which a new log file is started
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+}
+
+impl LogRotation {
+    fn rotation(self) -> Rotation {
+        match self {
+            Self::Hourly => Rotation::HOURLY,
+            Self::Daily => Rotation::DAILY,
+        }
+    }
+}
+
+impl std::fmt::Display for LogRotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hourly => write!(f, "hourly"),
+            Self::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+/// How the log files are rotated and how long they are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogPolicy {
+    pub rotation: LogRotation,
+    /// Files older than this are deleted
+    pub max_age: Option<Duration>,
+    /// Oldest files are deleted while the total size of the files exceeds this
+    pub max_size: Option<u64>,
+    /// Number of files kept for each log
+    pub max_files: Option<usize>,
+    /// Compresses rotated files with gzip
+    pub compress: bool,
+}
+
+impl Default for LogPolicy {
+    fn default() -> Self {
+        Self {
+            rotation: LogRotation::Daily,
+            max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            max_size: None,
+            max_files: None,
+            compress: false,
+        }
+    }
+}
+
+impl std::fmt::Display for LogPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rotation: {}, Max Age: ", self.rotation)?;
+        match self.max_age {
+            Some(v) => write!(f, "{}", humantime::format_duration(v))?,
+            None => write!(f, "None")?,
+        }
+        write!(f, ", Max Size: ")?;
+        match self.max_size {
+            Some(v) => write!(f, "{v} bytes")?,
+            None => write!(f, "None")?,
+        }
+        write!(f, ", Max Files: ")?;
+        match self.max_files {
+            Some(v) => write!(f, "{v}")?,
+            None => write!(f, "None")?,
+        }
+        write!(f, ", Compress: {}", self.compress)
+    }
+}
+
+/// Parses a size such as "1048576", "512K", "100M" or "1G" (in units of 1024)
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    let unit: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(Error::InvalidSize(text.to_string())),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|x| x.checked_mul(unit))
+        .ok_or_else(|| Error::InvalidSize(text.to_string()))
+}
+
+/// Rotated log file
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    prefix: &'static str,
+    time: chrono::NaiveDateTime,
+    compressed: bool,
+    size: u64,
+}
+
+impl LogFile {
+    /// Parses "{prefix}.YYYY-MM-DD" or "{prefix}.YYYY-MM-DD-HH", optionally followed by ".gz"
+    fn parse_time(suffix: &str) -> Option<chrono::NaiveDateTime> {
+        if let Ok(v) = chrono::NaiveDate::parse_from_str(suffix, "%Y-%m-%d") {
+            return v.and_hms_opt(0, 0, 0);
+        }
+        chrono::NaiveDateTime::parse_from_str(&format!("{suffix}:00"), "%Y-%m-%d-%H:%M").ok()
+    }
+
+    fn list(log_dir: &Path) -> Result<Vec<Self>> {
+        let mut ret = Vec::new();
+        for entry in fs::read_dir(log_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(prefix) = [LOGFILE_PREFIX, JSON_LOGFILE_PREFIX, QUERY_LOGFILE_PREFIX]
+                .into_iter()
+                .find(|x| name.starts_with(&format!("{x}.")))
+            else {
+                continue;
+            };
+            let suffix = &name[prefix.len() + 1..];
+            let (suffix, compressed) = match suffix.strip_suffix(".gz") {
+                Some(v) => (v, true),
+                None => (suffix, false),
+            };
+            match Self::parse_time(suffix) {
+                Some(time) => ret.push(Self {
+                    path: entry.path(),
+                    prefix,
+                    time,
+                    compressed,
+                    size: entry.metadata()?.len(),
+                }),
+                None => tracing::warn!("Failed to get date from file name ({name})"),
+            }
+        }
+
+        // Newest first
+        ret.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.path.cmp(&b.path)));
+        Ok(ret)
+    }
+
+    fn compress(&self) -> Result<PathBuf> {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        let mut name = self.path.clone().into_os_string();
+        name.push(".gz");
+        let path = PathBuf::from(name);
+        let mut input = fs::File::open(&self.path)?;
+        let mut encoder = GzEncoder::new(fs::File::create(&path)?, Compression::default());
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        fs::remove_file(&self.path)?;
+        Ok(path)
+    }
+}
+
+/// Compresses the rotated files and deletes the files exceeding the policy.
+/// The newest file of each log, which is being written, is left untouched.
+fn maintain(log_dir: &Path, policy: &LogPolicy, now: chrono::NaiveDateTime) -> Result<()> {
+    let files = LogFile::list(log_dir)?;
+    let mut seen: Vec<(&str, usize)> = Vec::new();
+    let mut kept: Vec<LogFile> = Vec::new();
+    let mut targets: Vec<LogFile> = Vec::new();
+    for file in files {
+        let index = match seen.iter_mut().find(|(p, _)| *p == file.prefix) {
+            Some((_, n)) => {
+                *n += 1;
+                *n
+            }
+            None => {
+                seen.push((file.prefix, 0));
+                0
+            }
+        };
+        if index == 0 {
+            kept.push(file);
+            continue;
+        }
+
+        let expired = policy
+            .max_age
+            .and_then(|x| chrono::Duration::from_std(x).ok())
+            .and_then(|x| now.checked_sub_signed(x))
+            .is_some_and(|x| file.time < x);
+        let exceeded = policy.max_files.is_some_and(|x| index >= x.max(1));
+        if expired || exceeded {
+            targets.push(file);
+        } else {
+            kept.push(file);
+        }
+    }
+
+    if let Some(max_size) = policy.max_size {
+        let mut total: u64 = kept.iter().map(|x| x.size).sum();
+        // `kept` is newest first, so the oldest files are removed from the end
+        while total > max_size {
+            let Some(i) = kept.iter().rposition(|x| {
+                kept.iter()
+                    .filter(|y| y.prefix == x.prefix)
+                    .any(|y| y.time > x.time)
+            }) else {
+                break;
+            };
+            let file = kept.remove(i);
+            total = total.saturating_sub(file.size);
+            targets.push(file);
+        }
+    }
+
+    let mut deleted = 0;
+    for file in targets.iter() {
+        match fs::remove_file(&file.path) {
+            Ok(_) => {
+                tracing::info!("Deleted {}", file.path.display());
+                deleted += 1;
+            }
+            Err(e) => tracing::warn!("Failed to delete {} ({e})", file.path.display()),
+        }
+    }
+    if deleted > 0 {
+        tracing::info!("Deleted {deleted} log files");
+    }
+
+    if policy.compress {
+        for file in kept.iter().filter(|x| !x.compressed) {
+            let is_newest = !kept
+                .iter()
+                .any(|y| y.prefix == file.prefix && y.time > file.time);
+            if is_newest {
+                continue;
+            }
+            match file.compress() {
+                Ok(path) => tracing::info!("Compressed {}", path.display()),
+                Err(e) => tracing::warn!("Failed to compress {} ({e})", file.path.display()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub struct LogContext {
+    /// Changes the level of the diagnostic log
     pub reload_handle: ReloadHandle,
-    pub file_guard: Option<non_blocking::WorkerGuard>,
+    pub file_guards: Vec<non_blocking::WorkerGuard>,
     log_dir: Option<PathBuf>,
+    policy: LogPolicy,
 }
 
 impl LogContext {
     fn new(
         reload_handle: ReloadHandle,
-        file_guard: Option<non_blocking::WorkerGuard>,
+        file_guards: Vec<non_blocking::WorkerGuard>,
         log_dir: Option<PathBuf>,
+        policy: LogPolicy,
     ) -> Self {
         Self {
             reload_handle,
-            file_guard,
+            file_guards,
             log_dir,
+            policy,
         }
     }
 
+    /// Compresses and deletes the old log files according to the policy
     pub fn remove_old_logs(&self) -> Result<()> {
         if let Some(log_dir) = self.log_dir.as_ref() {
-            let threshold = chrono::Local::now()
-                .checked_sub_months(chrono::Months::new(1))
-                .ok_or(Error::DeleteLogFiles)?;
-            let threshold = threshold.date_naive();
             tracing::info!(
-                "Deleting old log files from {} (threshold={})",
+                "Checking old log files in {} ({})",
                 log_dir.display(),
-                threshold
+                self.policy
             );
-            let entries = fs::read_dir(log_dir)?;
-            let mut targets = Vec::default();
-
-            for entry in entries {
-                let entry = entry?;
-                if entry.file_type()?.is_file() {
-                    let name = entry.file_name().to_string_lossy().into_owned();
-                    if name.starts_with(LOGFILE_PREFIX) {
-                        targets.push(name);
-                    }
-                }
-            }
+            maintain(log_dir, &self.policy, chrono::Local::now().naive_local())?;
+        }
 
-            targets.sort();
-            let mut deleted = 0;
-            for v in targets
-                .iter()
-                .filter(|x| x.len() > LOGFILE_PREFIX.len() + 1)
-            {
-                let file_date = &v[LOGFILE_PREFIX.len() + 1..];
-                match chrono::NaiveDate::parse_from_str(file_date, "%Y-%m-%d") {
-                    Ok(file_date) => {
-                        if file_date < threshold {
-                            let filepath = Path::join(log_dir, v);
-                            match std::fs::remove_file(&filepath) {
-                                Ok(_) => {
-                                    tracing::info!("Deleted {}", filepath.display());
-                                    deleted += 1;
-                                }
-                                Err(e) => {
-                                    tracing::warn!("Failed to delete {} ({e})", filepath.display());
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("Failed to get date from file name ({v}: {e})")
-                    }
-                }
+        Ok(())
+    }
+
+    /// Runs `remove_old_logs` periodically in the background
+    pub fn spawn_maintainer(&self) {
+        let Some(log_dir) = self.log_dir.clone() else {
+            return;
+        };
+        let policy = self.policy.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(MAINTENANCE_INTERVAL);
+            if let Err(e) = maintain(&log_dir, &policy, chrono::Local::now().naive_local()) {
+                tracing::error!("Failed to maintain log files ({e})");
             }
+        });
+    }
+}
 
-            tracing::info!("Deleted {deleted} log files");
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Where the logs are written in addition to the files in the log directory
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum LogOutput {
+    #[default]
+    Stdout,
+    Journald,
+    Syslog(SyslogTarget),
+}
+
+impl std::fmt::Display for LogOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Journald => write!(f, "journald"),
+            Self::Syslog(target) => write!(f, "syslog ({target})"),
         }
+    }
+}
 
-        Ok(())
+impl LogOutput {
+    fn layer(&self, query: bool) -> Result<BoxedLayer> {
+        let ret = match self {
+            Self::Stdout => {
+                let ansi = std::io::stdout().is_terminal();
+                fmt_layer(query, ansi, std::io::stdout)
+            }
+            Self::Journald => tracing_journald::layer()?
+                .with_field_prefix(None)
+                .with_syslog_identifier(APP_NAME.into())
+                .boxed(),
+            Self::Syslog(target) => SyslogLayer::new(target, APP_NAME)?.boxed(),
+        };
+        Ok(ret)
     }
 }
 
-pub fn init(level: tracing::Level, log_dir: Option<impl AsRef<Path>>) -> LogContext {
+/// Returns a layer that formats the events as text.
+/// The fields of the queries are omitted since the message contains them.
+fn fmt_layer<W>(query: bool, ansi: bool, writer: W) -> BoxedLayer
+where
+    W: for<'a> fmt::MakeWriter<'a> + Send + Sync + 'static,
+{
     let format = tracing_subscriber::fmt::format()
         .with_level(true)
         .with_target(false)
         .with_thread_ids(true)
-        .with_ansi(true)
+        .with_ansi(ansi)
         .compact();
+    let layer = fmt::Layer::default()
+        .event_format(format)
+        .with_ansi(ansi)
+        .with_writer(writer);
+    if query {
+        layer
+            .fmt_fields(fmt::format::debug_fn(|w, field, value| {
+                if field.name() == "message" {
+                    write!(w, "{value:?}")
+                } else {
+                    Ok(())
+                }
+            }))
+            .boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
+/// Initializes the diagnostic log and the query log.
+/// With `log_dir`, the queries are written to their own files instead of stdout.
+pub fn init(
+    level: tracing::Level,
+    query_level: LevelFilter,
+    log_dir: Option<impl AsRef<Path>>,
+    policy: LogPolicy,
+    output: LogOutput,
+) -> Result<LogContext> {
     let filter = filter::LevelFilter::from_level(level);
     let (filter_layer, reload_handle) = reload::Layer::new(filter);
-    let subscriber = tracing_subscriber::registry().with(filter_layer);
-    let stdout_layer = fmt::Layer::default().event_format(format.clone());
-    let subscriber = subscriber.with(stdout_layer);
-    if let Some(log_dir) = log_dir {
-        let log_dir = log_dir.as_ref();
-        let file_appender = RollingFileAppender::new(Rotation::DAILY, log_dir, LOGFILE_PREFIX);
+    // The level set by the `log` command must not hide the audit log
+    let diagnostic_filter = filter_layer
+        .and(filter::filter_fn(|meta| meta.target() != QUERY_TARGET))
+        .or(filter::filter_fn(|meta| meta.target() == AUDIT_TARGET));
+    let query_filter = filter::Targets::new().with_target(QUERY_TARGET, query_level);
+
+    let mut diagnostic_layers = vec![output.layer(false)?];
+    let mut query_layers = Vec::new();
+    if log_dir.is_none() || output != LogOutput::Stdout {
+        query_layers.push(output.layer(true)?);
+    }
+
+    let mut file_guards = Vec::new();
+    let log_dir = log_dir.map(|x| x.as_ref().to_path_buf());
+    if let Some(log_dir) = log_dir.as_ref() {
+        let file_appender =
+            RollingFileAppender::new(policy.rotation.rotation(), log_dir, LOGFILE_PREFIX);
         let (non_blocking_file_appender, guard) = tracing_appender::non_blocking(file_appender);
-        let file_layer = fmt::Layer::default()
-            .event_format(format)
-            .with_writer(non_blocking_file_appender);
-        subscriber.with(file_layer).init();
-        LogContext::new(reload_handle, Some(guard), Some(log_dir.to_path_buf()))
+        diagnostic_layers.push(fmt_layer(false, false, non_blocking_file_appender));
+        file_guards.push(guard);
+
+        let query_appender =
+            RollingFileAppender::new(policy.rotation.rotation(), log_dir, QUERY_LOGFILE_PREFIX);
+        let (non_blocking_query_appender, guard) = tracing_appender::non_blocking(query_appender);
+        query_layers.push(fmt_layer(true, false, non_blocking_query_appender));
+        file_guards.push(guard);
+    }
+
+    tracing_subscriber::registry()
+        .with(vec![
+            diagnostic_layers.with_filter(diagnostic_filter).boxed(),
+            query_layers.with_filter(query_filter).boxed(),
+        ])
+        .init();
+    Ok(LogContext::new(reload_handle, file_guards, log_dir, policy))
+}
+
+/// Returns the writer of the JSON query log.
+/// The log is written to a rotated file in `log_dir`, or to stdout if it is `None`.
+pub fn json_writer(
+    log_dir: Option<impl AsRef<Path>>,
+    rotation: LogRotation,
+) -> (non_blocking::NonBlocking, non_blocking::WorkerGuard) {
+    if let Some(log_dir) = log_dir {
+        let file_appender =
+            RollingFileAppender::new(rotation.rotation(), log_dir.as_ref(), JSON_LOGFILE_PREFIX);
+        tracing_appender::non_blocking(file_appender)
     } else {
-        subscriber.init();
-        LogContext::new(reload_handle, None, None)
+        tracing_appender::non_blocking(std::io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(1000, parse_size("1000").unwrap());
+        assert_eq!(512 * 1024, parse_size("512K").unwrap());
+        assert_eq!(100 * 1024 * 1024, parse_size("100MiB").unwrap());
+        assert_eq!(1 << 30, parse_size("1 G").unwrap());
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_maintain() {
+        let dir = std::env::temp_dir().join(format!("ldf-test-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "local-dns-forwarder.log.2024-01-01",
+            "local-dns-forwarder.log.2024-01-10",
+            "local-dns-forwarder.log.2024-01-11-09",
+            "local-dns-forwarder.log.2024-01-11-10",
+            "local-dns-forwarder.json.2024-01-11",
+            "unrelated.txt",
+        ];
+        for name in names {
+            fs::write(dir.join(name), "0123456789").unwrap();
+        }
+        let list = |dir: &Path| {
+            let mut ret = fs::read_dir(dir)
+                .unwrap()
+                .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            ret.sort();
+            ret
+        };
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 1, 11)
+            .unwrap()
+            .and_hms_opt(10, 30, 0)
+            .unwrap();
+
+        // The newest file of each log is kept even if it is too old
+        let policy = LogPolicy {
+            max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            compress: true,
+            ..Default::default()
+        };
+        maintain(&dir, &policy, now).unwrap();
+        assert_eq!(
+            vec![
+                "local-dns-forwarder.json.2024-01-11",
+                "local-dns-forwarder.log.2024-01-10.gz",
+                "local-dns-forwarder.log.2024-01-11-09.gz",
+                "local-dns-forwarder.log.2024-01-11-10",
+                "unrelated.txt",
+            ],
+            list(&dir)
+        );
+
+        let policy = LogPolicy {
+            max_files: Some(2),
+            ..Default::default()
+        };
+        maintain(&dir, &policy, now).unwrap();
+        assert_eq!(
+            vec![
+                "local-dns-forwarder.json.2024-01-11",
+                "local-dns-forwarder.log.2024-01-11-09.gz",
+                "local-dns-forwarder.log.2024-01-11-10",
+                "unrelated.txt",
+            ],
+            list(&dir)
+        );
+
+        let policy = LogPolicy {
+            max_size: Some(1),
+            ..Default::default()
+        };
+        maintain(&dir, &policy, now).unwrap();
+        assert_eq!(
+            vec![
+                "local-dns-forwarder.json.2024-01-11",
+                "local-dns-forwarder.log.2024-01-11-10",
+                "unrelated.txt",
+            ],
+            list(&dir)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,226 @@
+use crate::filters::CompositeCheckList;
+use crate::query_context::QueryContext;
+use crate::resolve_event::ResolveEvent;
+use crate::resolved_status::ResolvedStatus;
+use crate::Result;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec};
+use prometheus::{Opts, Registry, TextEncoder};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+
+/// Counters and histograms exported in the Prometheus text format
+pub struct Metrics {
+    registry: Registry,
+    queries: IntCounterVec,
+    upstream_latency: HistogramVec,
+    upstream_errors: IntCounterVec,
+    list_entries: IntGaugeVec,
+    in_flight: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("ldf".into()), None)?;
+        let queries = IntCounterVec::new(
+            Opts::new("queries_total", "Number of queries"),
+            &["qtype", "decision", "rcode"],
+        )?;
+        let upstream_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_latency_seconds",
+                "Time taken by the upstream DNS server to respond",
+            )
+            .buckets(vec![
+                0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["server"],
+        )?;
+        let upstream_errors = IntCounterVec::new(
+            Opts::new(
+                "upstream_errors_total",
+                "Number of queries the upstream DNS server did not respond to",
+            ),
+            &["server"],
+        )?;
+        let list_entries = IntGaugeVec::new(
+            Opts::new("list_entries", "Number of entries in the lists"),
+            &["list"],
+        )?;
+        let in_flight = IntGauge::new("in_flight_requests", "Number of queries being handled")?;
+
+        registry.register(Box::new(queries.clone()))?;
+        registry.register(Box::new(upstream_latency.clone()))?;
+        registry.register(Box::new(upstream_errors.clone()))?;
+        registry.register(Box::new(list_entries.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        Ok(Self {
+            registry,
+            queries,
+            upstream_latency,
+            upstream_errors,
+            list_entries,
+            in_flight,
+        })
+    }
+
+    /// Same as `ResolveEvent::resolved` but takes the status by reference
+    pub fn resolved_ref(&self, ctx: &QueryContext, status: &ResolvedStatus) {
+        self.in_flight.dec();
+        self.observe(ctx, status);
+    }
+
+    pub fn observe(&self, ctx: &QueryContext, status: &ResolvedStatus) {
+        let qtype = ctx.qtype.to_string();
+        let rcode = format!("{:?}", status.rescode());
+        self.queries
+            .with_label_values(&[qtype.as_str(), status.decision(), rcode.as_str()])
+            .inc();
+
+        if let Some(upstream) = ctx.upstream {
+            let server = upstream.to_string();
+            if let Some(latency) = ctx.upstream_latency {
+                self.upstream_latency
+                    .with_label_values(&[server.as_str()])
+                    .observe(latency.as_secs_f64());
+            } else {
+                self.upstream_errors
+                    .with_label_values(&[server.as_str()])
+                    .inc();
+            }
+        }
+    }
+
+    /// Updates the number of entries of each list
+    pub fn update_lists(&self, checklist: &CompositeCheckList) {
+        let set = |name: &str, count: usize| {
+            self.list_entries
+                .with_label_values(&[name])
+                .set(count as i64);
+        };
+        set("allowlist", checklist.allowlist.count());
+        set("denylist", checklist.denylist.count());
+        for (i, list) in checklist.blocklists.iter().enumerate() {
+            set(&format!("blocklist:{i}"), list.count());
+        }
+        for (name, list) in checklist
+            .remote_allowlists
+            .iter()
+            .chain(checklist.remote_denylists.iter())
+        {
+            set(&format!("subscription:{name}"), list.count());
+        }
+        for v in checklist.scheduled.iter() {
+            set(&format!("scheduled:{}", v.name), v.list.count());
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format
+    pub fn render(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+impl ResolveEvent for Metrics {
+    fn resolving(&self, _ctx: &QueryContext) {
+        self.in_flight.inc();
+    }
+
+    fn resolved(&self, ctx: &QueryContext, status: ResolvedStatus) {
+        self.resolved_ref(ctx, &status);
+    }
+
+    fn aborted(&self, _ctx: &QueryContext) {
+        self.in_flight.dec();
+    }
+}
+
+/// Spawns a thread that serves the metrics on `http://{addr}/metrics`
+pub fn spawn_server(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    checklist: Arc<RwLock<CompositeCheckList>>,
+) -> Result<JoinHandle<()>> {
+    let server = tiny_http::Server::http(addr).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let handle = std::thread::spawn(move || {
+        for req in server.incoming_requests() {
+            let resp = if req.url() != "/metrics" {
+                tiny_http::Response::from_string("Not Found").with_status_code(404)
+            } else {
+                if let Ok(checklist) = checklist.read() {
+                    metrics.update_lists(&checklist);
+                }
+                match metrics.render() {
+                    Ok(body) => tiny_http::Response::from_string(body).with_header(
+                        tiny_http::Header::from_bytes(
+                            &b"Content-Type"[..],
+                            &b"text/plain; version=0.0.4"[..],
+                        )
+                        .expect("valid header"),
+                    ),
+                    Err(e) => {
+                        tracing::error!("Failed to render metrics ({e})");
+                        tiny_http::Response::from_string("Internal Server Error")
+                            .with_status_code(500)
+                    }
+                }
+            };
+            if let Err(e) = req.respond(resp) {
+                tracing::warn!("Failed to send metrics ({e})");
+            }
+        }
+    });
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{QueryType, ResultCode};
+    use crate::resolved_data::ResolvedData;
+    use crate::CheckList;
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new().unwrap();
+        let client = "192.168.1.10:50000".parse().unwrap();
+
+        let mut ctx = QueryContext::new(1, client, QueryType::A, "www.example.com", Instant::now());
+        metrics.resolving(&ctx);
+        ctx.upstream = Some(Ipv4Addr::new(8, 8, 8, 8));
+        ctx.upstream_latency = Some(Duration::from_millis(3));
+        let data = ResolvedData::new(QueryType::A, "www.example.com");
+        metrics.resolved(&ctx, ResolvedStatus::Allow(data, None));
+
+        let mut ctx = QueryContext::new(2, client, QueryType::A, "www.example.org", Instant::now());
+        ctx.upstream = Some(Ipv4Addr::new(8, 8, 8, 8));
+        let data = ResolvedData::new(QueryType::A, "www.example.org");
+        metrics.observe(
+            &ctx,
+            &ResolvedStatus::AllowButError(data, ResultCode::ServFail, None),
+        );
+
+        // A query that failed before the response is not counted as in flight
+        let ctx = QueryContext::new(3, client, QueryType::A, "www.example.net", Instant::now());
+        metrics.resolving(&ctx);
+        metrics.aborted(&ctx);
+
+        let mut allowlist = CheckList::in_memory();
+        allowlist.add("www.example.com");
+        metrics.update_lists(&CompositeCheckList::new(allowlist, CheckList::in_memory()));
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(r#"ldf_queries_total{decision="allow",qtype="A",rcode="NoError"} 1"#));
+        assert!(
+            text.contains(r#"ldf_queries_total{decision="allow",qtype="A",rcode="ServFail"} 1"#)
+        );
+        assert!(text.contains(r#"ldf_upstream_latency_seconds_count{server="8.8.8.8"} 1"#));
+        assert!(text.contains(r#"ldf_upstream_errors_total{server="8.8.8.8"} 1"#));
+        assert!(text.contains(r#"ldf_list_entries{list="allowlist"} 1"#));
+        assert!(text.contains("ldf_in_flight_requests 0"));
+    }
+}
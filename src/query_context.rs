@@ -0,0 +1,43 @@
+use crate::dns::QueryType;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Information about a query, passed to `ResolveEvent` along with the result
+#[derive(Debug, Clone)]
+pub struct QueryContext {
+    /// Transaction ID of the query
+    pub id: u16,
+    /// Address of the client that sent the query
+    pub client: SocketAddr,
+    pub qtype: QueryType,
+    pub qname: String,
+    /// DNS server the query was forwarded to, `None` until it is forwarded
+    pub upstream: Option<Ipv4Addr>,
+    /// Time taken by the DNS server to respond, `None` if it did not respond
+    pub upstream_latency: Option<Duration>,
+    /// Time the query was received
+    pub received: Instant,
+    /// Time taken from receiving the query to making the response
+    pub elapsed: Duration,
+}
+
+impl QueryContext {
+    pub(crate) fn new(
+        id: u16,
+        client: SocketAddr,
+        qtype: QueryType,
+        qname: impl Into<String>,
+        received: Instant,
+    ) -> Self {
+        Self {
+            id,
+            client,
+            qtype,
+            qname: qname.into(),
+            upstream: None,
+            upstream_latency: None,
+            received,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
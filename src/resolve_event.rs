@@ -1,20 +1,26 @@
+use crate::logger::QUERY_TARGET;
+use crate::query_context::QueryContext;
 use crate::resolved_status::ResolvedStatus;
 
 pub trait ResolveEvent {
-    fn resolving(&self, name: &str);
-    fn resolved(&self, status: ResolvedStatus);
+    /// Called before the query is checked and forwarded
+    fn resolving(&self, ctx: &QueryContext);
+    /// Called after the response is made
+    fn resolved(&self, ctx: &QueryContext, status: ResolvedStatus);
+    /// Called instead of `resolved` when handling the query failed before the response is made
+    fn aborted(&self, _ctx: &QueryContext) {}
     fn error(&self, _message: impl AsRef<str>) {}
 }
 
 pub struct DefaultResolveEvent;
 
 impl ResolveEvent for DefaultResolveEvent {
-    fn resolving(&self, name: &str) {
-        println!("[Resolving] {name}");
+    fn resolving(&self, ctx: &QueryContext) {
+        println!("[Resolving] {}", ctx.qname);
     }
 
-    fn resolved(&self, status: ResolvedStatus) {
-        tracing::info!("{status}")
+    fn resolved(&self, _ctx: &QueryContext, status: ResolvedStatus) {
+        tracing::info!(target: QUERY_TARGET, "{status}")
     }
 
     fn error(&self, message: impl AsRef<str>) {
@@ -24,12 +30,12 @@ impl ResolveEvent for DefaultResolveEvent {
 
 pub struct TracingResolveEvent;
 impl ResolveEvent for TracingResolveEvent {
-    fn resolving(&self, name: &str) {
-        tracing::info!("[Resolving] {name}");
+    fn resolving(&self, ctx: &QueryContext) {
+        tracing::info!(target: QUERY_TARGET, "[Resolving] {}", ctx.qname);
     }
 
-    fn resolved(&self, status: ResolvedStatus) {
-        tracing::info!("{status}")
+    fn resolved(&self, _ctx: &QueryContext, status: ResolvedStatus) {
+        tracing::info!(target: QUERY_TARGET, "{status}")
     }
 
     fn error(&self, message: impl AsRef<str>) {
@@ -1,29 +1,50 @@
 use crate::dns::ResultCode;
+use crate::filters::CheckMatch;
 use crate::resolved_data::ResolvedData;
 use std::fmt::Display;
 
 /// Represents the result of a name resolution
 pub enum ResolvedStatus {
-    /// Indicates that the FQDN is not listed in the allowlist and has been denied
-    Deny(ResolvedData, ResultCode),
-    /// Indicates that the FQDN is listed in the allowlist and has been resolved
-    Allow(ResolvedData),
+    /// Indicates that the FQDN has been denied, with the entry that denied it if it is listed
+    Deny(ResolvedData, ResultCode, Option<CheckMatch>),
+    /// Indicates that the FQDN is listed in the allowlist and has been resolved, with the entry that allowed it
+    Allow(ResolvedData, Option<CheckMatch>),
     /// Indicates that the FQDN is listed in the allowlist but the name resolution failed
-    AllowButError(ResolvedData, ResultCode),
+    AllowButError(ResolvedData, ResultCode, Option<CheckMatch>),
     /// Indicates that the name resolution was performed without checking the allowlist
     NoCheck(ResolvedData),
     /// Indicates that the name resolution failed without checking the allowlist
     NoCheckButError(ResolvedData, ResultCode),
+    /// Indicates that the FQDN is not listed and has been resolved because unlisted FQDNs are allowed
+    Unlisted(ResolvedData),
+    /// Indicates that the FQDN is not listed and the name resolution failed
+    UnlistedButError(ResolvedData, ResultCode),
+    /// Indicates that the FQDN is not listed but has been resolved in learning mode
+    Learned(ResolvedData),
+    /// Indicates that the FQDN is not listed and the name resolution failed in learning mode
+    LearnedButError(ResolvedData, ResultCode),
 }
 
 impl ResolvedStatus {
     pub fn pretty_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Deny(v, code) => write!(f, "[Deny] <{}> {}: {code}", v.req_qtype, v.req_name),
-            Self::AllowButError(v, code) => {
+            Self::Deny(v, code, m) => {
+                write!(f, "[Deny] <{}> {}: {code}", v.req_qtype, v.req_name)?;
+                if let Some(m) = m {
+                    write!(f, " ({} in {}", m.rule, m.list)?;
+                    match (m.path.as_ref(), m.line) {
+                        (Some(path), Some(line)) => write!(f, ", {}:{line}", path.display())?,
+                        (Some(path), None) => write!(f, ", {}", path.display())?,
+                        _ => (),
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Self::AllowButError(v, code, _) => {
                 write!(f, "[Allow] <{}> {}: {code}", v.req_qtype, v.req_name)
             }
-            Self::Allow(v) => {
+            Self::Allow(v, _) => {
                 write!(f, "[Allow] ")?;
                 v.pretty_fmt(f)?;
                 Ok(())
@@ -36,13 +57,99 @@ impl ResolvedStatus {
             Self::NoCheckButError(v, code) => {
                 write!(f, "[NoCheck] <{}> {}: {code}", v.req_qtype, v.req_name)
             }
+            Self::Unlisted(v) => {
+                write!(f, "[Unlisted] ")?;
+                v.pretty_fmt(f)?;
+                Ok(())
+            }
+            Self::UnlistedButError(v, code) => {
+                write!(f, "[Unlisted] <{}> {}: {code}", v.req_qtype, v.req_name)
+            }
+            Self::Learned(v) => {
+                write!(f, "[Learned] ")?;
+                v.pretty_fmt(f)?;
+                Ok(())
+            }
+            Self::LearnedButError(v, code) => {
+                write!(f, "[Learned] <{}> {}: {code}", v.req_qtype, v.req_name)
+            }
+        }
+    }
+
+    pub fn data(&self) -> &ResolvedData {
+        match self {
+            Self::Deny(v, ..)
+            | Self::Allow(v, _)
+            | Self::AllowButError(v, ..)
+            | Self::NoCheck(v)
+            | Self::NoCheckButError(v, _)
+            | Self::Unlisted(v)
+            | Self::UnlistedButError(v, _)
+            | Self::Learned(v)
+            | Self::LearnedButError(v, _) => v,
+        }
+    }
+
+    /// Returns the response code sent to the client
+    pub fn rescode(&self) -> ResultCode {
+        match self {
+            Self::Deny(_, code, _)
+            | Self::AllowButError(_, code, _)
+            | Self::NoCheckButError(_, code)
+            | Self::UnlistedButError(_, code)
+            | Self::LearnedButError(_, code) => *code,
+            _ => ResultCode::NoError,
+        }
+    }
+
+    /// Returns the short name of the decision (e.g. `allow`, `deny`)
+    pub fn decision(&self) -> &'static str {
+        match self {
+            Self::Deny(..) => "deny",
+            Self::Allow(..) | Self::AllowButError(..) => "allow",
+            Self::NoCheck(_) | Self::NoCheckButError(..) => "nocheck",
+            Self::Unlisted(_) | Self::UnlistedButError(..) => "unlisted",
+            Self::Learned(_) | Self::LearnedButError(..) => "learned",
+        }
+    }
+
+    /// Returns the entry that allowed or denied the FQDN
+    pub fn matched(&self) -> Option<&CheckMatch> {
+        match self {
+            Self::Deny(_, _, m) | Self::Allow(_, m) | Self::AllowButError(_, _, m) => m.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Sets the entry that allowed the FQDN
+    pub(super) fn with_match(self, m: Option<CheckMatch>) -> ResolvedStatus {
+        match self {
+            Self::Allow(v, _) => Self::Allow(v, m),
+            Self::AllowButError(v, code, _) => Self::AllowButError(v, code, m),
+            v => v,
         }
     }
 
     pub(super) fn into_nocheck(self) -> ResolvedStatus {
         match self {
-            Self::Allow(v) => ResolvedStatus::NoCheck(v),
-            Self::AllowButError(v, code) => ResolvedStatus::NoCheckButError(v, code),
+            Self::Allow(v, _) => ResolvedStatus::NoCheck(v),
+            Self::AllowButError(v, code, _) => ResolvedStatus::NoCheckButError(v, code),
+            v => v,
+        }
+    }
+
+    pub(super) fn into_unlisted(self) -> ResolvedStatus {
+        match self {
+            Self::Allow(v, _) => ResolvedStatus::Unlisted(v),
+            Self::AllowButError(v, code, _) => ResolvedStatus::UnlistedButError(v, code),
+            v => v,
+        }
+    }
+
+    pub(super) fn into_learned(self) -> ResolvedStatus {
+        match self {
+            Self::Allow(v, _) => ResolvedStatus::Learned(v),
+            Self::AllowButError(v, code, _) => ResolvedStatus::LearnedButError(v, code),
             v => v,
         }
     }
@@ -1,17 +1,26 @@
 use crate::dns;
-use crate::filters::{CheckStatus, CompositeCheckList};
+use crate::filters::{CheckMatch, CheckStatus, ClientGroup, CompositeCheckList, DefaultAction};
+use crate::learned_names::LearnedNames;
+use crate::query_context::QueryContext;
 use crate::resolve_event::{DefaultResolveEvent, ResolveEvent};
 use crate::resolved_status::ResolvedStatus;
 use serde::Deserialize;
 use std::fmt::Display;
-use std::net::{Ipv4Addr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     address: String,
     port: u16,
     default_dns_server: Ipv4Addr,
+    /// Action taken for FQDNs that are in neither the allowlist nor the denylist
+    #[serde(default, rename = "default")]
+    default_action: DefaultAction,
+    /// Resolves FQDNs that are not listed and records them instead of denying them
+    #[serde(default)]
+    learning: bool,
 }
 
 impl Config {
@@ -20,8 +29,15 @@ impl Config {
             address: address.into(),
             port,
             default_dns_server: Ipv4Addr::new(8, 8, 8, 8),
+            default_action: DefaultAction::Deny,
+            learning: false,
         }
     }
+
+    /// Returns the address and the port the server binds to
+    pub fn bind_address(&self) -> (&str, u16) {
+        (&self.address, self.port)
+    }
 }
 
 impl Default for Config {
@@ -30,6 +46,8 @@ impl Default for Config {
             address: "127.0.0.1".into(),
             port: 53,
             default_dns_server: Ipv4Addr::new(8, 8, 8, 8),
+            default_action: DefaultAction::Deny,
+            learning: false,
         }
     }
 }
@@ -38,8 +56,8 @@ impl Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Address: {}, Port: {}, Default DNS Server: {}",
-            self.address, self.port, self.default_dns_server
+            "Address: {}, Port: {}, Default DNS Server: {}, Default: {}, Learning: {}",
+            self.address, self.port, self.default_dns_server, self.default_action, self.learning
         )
     }
 }
@@ -47,6 +65,7 @@ impl Display for Config {
 pub struct ServerBuilder<E: ResolveEvent> {
     config: Config,
     checklist: CompositeCheckList,
+    client_groups: Vec<ClientGroup>,
     event: E,
 }
 
@@ -58,6 +77,8 @@ impl<E: ResolveEvent> ServerBuilder<E> {
             default_dns_server: Arc::new(RwLock::new(default_dns_server)),
             event: self.event,
             checklist: Arc::new(RwLock::new(self.checklist)),
+            client_groups: Arc::new(RwLock::new(self.client_groups)),
+            learned: Arc::new(RwLock::new(LearnedNames::new())),
         }
     }
 
@@ -65,6 +86,16 @@ impl<E: ResolveEvent> ServerBuilder<E> {
         Self {
             config: self.config,
             checklist,
+            client_groups: self.client_groups,
+            event: self.event,
+        }
+    }
+
+    pub fn client_groups(self, client_groups: Vec<ClientGroup>) -> Self {
+        Self {
+            config: self.config,
+            checklist: self.checklist,
+            client_groups,
             event: self.event,
         }
     }
@@ -73,6 +104,7 @@ impl<E: ResolveEvent> ServerBuilder<E> {
 pub struct ServerConfigBuilder {
     config: Config,
     checklist: CompositeCheckList,
+    client_groups: Vec<ClientGroup>,
 }
 
 impl ServerConfigBuilder {
@@ -81,6 +113,7 @@ impl ServerConfigBuilder {
             config: self.config,
             event,
             checklist: self.checklist,
+            client_groups: self.client_groups,
         }
     }
 
@@ -88,6 +121,15 @@ impl ServerConfigBuilder {
         Self {
             config: self.config,
             checklist,
+            client_groups: self.client_groups,
+        }
+    }
+
+    pub fn client_groups(self, client_groups: Vec<ClientGroup>) -> Self {
+        Self {
+            config: self.config,
+            checklist: self.checklist,
+            client_groups,
         }
     }
 
@@ -102,6 +144,7 @@ impl Server {
         ServerConfigBuilder {
             config,
             checklist: Default::default(),
+            client_groups: Default::default(),
         }
     }
 }
@@ -111,6 +154,10 @@ pub struct Runner<E: ResolveEvent> {
     default_dns_server: Arc<RwLock<Ipv4Addr>>,
     event: E,
     pub checklist: Arc<RwLock<CompositeCheckList>>,
+    /// Clients that are checked against their own lists instead of `checklist`
+    pub client_groups: Arc<RwLock<Vec<ClientGroup>>>,
+    /// FQDNs resolved in learning mode although they are not listed
+    pub learned: Arc<RwLock<LearnedNames>>,
 }
 
 impl<E: ResolveEvent> Runner<E> {
@@ -127,37 +174,26 @@ impl<E: ResolveEvent> Runner<E> {
     fn on_recv(&self, socket: &UdpSocket) -> dns::Result<()> {
         let mut req_buffer = dns::BytePacketBuffer::new();
         let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
+        let started = Instant::now();
         let mut req = dns::Message::read(&mut req_buffer)?;
         let mut raw_buf = Vec::new();
 
         if let Some(question) = req.questions.pop() {
             let qtype = question.qtype;
             let name = question.name.clone();
-            if question.qtype == dns::QueryType::A || question.qtype == dns::QueryType::AAAA {
-                match self.check(&question.name) {
-                    CheckStatus::Deny => {
-                        // Ignore FQDNs that are registered in the deny list
-                        let (_, resp_buffer) =
-                            Self::make_error_resp_msg(&req, dns::ResultCode::NXDomain)?;
-                        raw_buf.extend(resp_buffer.get_all()?);
-                    }
-                    CheckStatus::Allow => {
-                        let status = self.lookup(req.header.id, question, &mut raw_buf)?;
-                        self.event.resolved(status);
-                    }
-                    CheckStatus::NotFound => {
-                        let (resp, resp_buffer) =
-                            Self::make_error_resp_msg(&req, dns::ResultCode::NXDomain)?;
-                        raw_buf.extend(resp_buffer.get_all()?);
-                        let res_data = crate::resolved_data::ResolvedData::new(qtype, name);
-                        self.event
-                            .resolved(ResolvedStatus::Deny(res_data, resp.header.rescode));
-                    }
+            let mut ctx = QueryContext::new(req.header.id, src, qtype, &name, started);
+            self.event.resolving(&ctx);
+            let status = match self.resolve(&req, question, &mut ctx, &mut raw_buf) {
+                Ok(v) => v,
+                Err(e) => {
+                    // Let the events that counted the query in `resolving` know it has ended
+                    self.event.aborted(&ctx);
+                    return Err(e);
                 }
-            } else {
-                let status = self.lookup(req.header.id, question, &mut raw_buf)?;
-                self.event.resolved(status.into_nocheck());
-            }
+            };
+
+            ctx.elapsed = started.elapsed();
+            self.event.resolved(&ctx, status);
         } else {
             let (resp, resp_buffer) = Self::make_error_resp_msg(&req, dns::ResultCode::FormErr)?;
             raw_buf.extend(resp_buffer.get_all()?);
@@ -170,19 +206,92 @@ impl<E: ResolveEvent> Runner<E> {
         Ok(())
     }
 
-    fn check(&self, name: &str) -> CheckStatus {
+    /// Checks the question and makes the response into `raw_buf`
+    fn resolve(
+        &self,
+        req: &dns::Message,
+        question: dns::Question,
+        ctx: &mut QueryContext,
+        raw_buf: &mut Vec<u8>,
+    ) -> dns::Result<ResolvedStatus> {
+        let qtype = question.qtype;
+        let name = question.name.clone();
+        let ret = if question.qtype == dns::QueryType::A || question.qtype == dns::QueryType::AAAA {
+            let (status, matched, default_action) = self.check(ctx.client.ip(), &question.name);
+            match (status, default_action) {
+                (CheckStatus::Deny, _) => {
+                    // Ignore FQDNs that are registered in the deny list
+                    let (resp, resp_buffer) =
+                        Self::make_error_resp_msg(req, dns::ResultCode::NXDomain)?;
+                    raw_buf.extend(resp_buffer.get_all()?);
+                    let res_data = crate::resolved_data::ResolvedData::new(qtype, name);
+                    ResolvedStatus::Deny(res_data, resp.header.rescode, matched)
+                }
+                (CheckStatus::Allow, _) => self.lookup(ctx, question, raw_buf)?.with_match(matched),
+                (CheckStatus::NotFound, DefaultAction::Allow) => {
+                    self.lookup(ctx, question, raw_buf)?.into_unlisted()
+                }
+                (CheckStatus::NotFound, DefaultAction::Deny) if self.config.learning => {
+                    if let Ok(mut learned) = self.learned.write() {
+                        learned.record(&question.name);
+                    } else {
+                        self.event
+                            .error("Failed to record learned name(write lock error)");
+                    }
+                    self.lookup(ctx, question, raw_buf)?.into_learned()
+                }
+                (CheckStatus::NotFound, DefaultAction::Deny) => {
+                    let (resp, resp_buffer) =
+                        Self::make_error_resp_msg(req, dns::ResultCode::NXDomain)?;
+                    raw_buf.extend(resp_buffer.get_all()?);
+                    let res_data = crate::resolved_data::ResolvedData::new(qtype, name);
+                    ResolvedStatus::Deny(res_data, resp.header.rescode, None)
+                }
+            }
+        } else {
+            self.lookup(ctx, question, raw_buf)?.into_nocheck()
+        };
+        Ok(ret)
+    }
+
+    /// Returns the status of the FQDN with the entry that decides it, and the action for unlisted FQDNs
+    fn check(
+        &self,
+        client: IpAddr,
+        name: &str,
+    ) -> (CheckStatus, Option<CheckMatch>, DefaultAction) {
+        let status = |m: &Option<CheckMatch>| {
+            m.as_ref()
+                .map_or(CheckStatus::NotFound, |x| x.status.clone())
+        };
+
+        if let Ok(client_groups) = self.client_groups.read() {
+            if let Some(group) = client_groups.iter().find(|x| x.contains(client)) {
+                let m = group.checklist.explain(name).map(|mut x| {
+                    x.list = format!("{}/{}", group.name, x.list);
+                    x
+                });
+                return (status(&m), m, group.default_action);
+            }
+        } else {
+            self.event
+                .error("Failed to get client groups(read lock error)");
+            return (CheckStatus::Deny, None, DefaultAction::Deny);
+        }
+
         if let Ok(checklist) = self.checklist.read() {
-            checklist.check(name)
+            let m = checklist.explain(name);
+            (status(&m), m, self.config.default_action)
         } else {
             self.event
                 .error("Failed to get allow list(read lock error)");
-            CheckStatus::Deny
+            (CheckStatus::Deny, None, DefaultAction::Deny)
         }
     }
 
     fn lookup(
         &self,
-        id: u16,
+        ctx: &mut QueryContext,
         question: dns::Question,
         raw: &mut Vec<u8>,
     ) -> dns::Result<ResolvedStatus> {
@@ -192,16 +301,19 @@ impl<E: ResolveEvent> Runner<E> {
             self.config.default_dns_server
         };
 
+        ctx.upstream = Some(dns_server);
         let mut res_data =
             crate::resolved_data::ResolvedData::new(question.qtype, question.name.clone());
 
+        let started = Instant::now();
         let ret = if let Ok((resp_buf, result)) = dns::lookup(
             dns_server,
-            id,
+            ctx.id,
             &question.name,
             question.qtype,
             question.class,
         ) {
+            ctx.upstream_latency = Some(started.elapsed());
             *raw = resp_buf;
 
             for rec in result.answers {
@@ -217,12 +329,12 @@ impl<E: ResolveEvent> Runner<E> {
             }
 
             if result.header.rescode == dns::ResultCode::NoError {
-                ResolvedStatus::Allow(res_data)
+                ResolvedStatus::Allow(res_data, None)
             } else {
-                ResolvedStatus::AllowButError(res_data, result.header.rescode)
+                ResolvedStatus::AllowButError(res_data, result.header.rescode, None)
             }
         } else {
-            ResolvedStatus::AllowButError(res_data, dns::ResultCode::ServFail)
+            ResolvedStatus::AllowButError(res_data, dns::ResultCode::ServFail, None)
         };
         Ok(ret)
     }
@@ -0,0 +1,226 @@
+use crate::query_context::QueryContext;
+use crate::resolved_status::ResolvedStatus;
+use chrono::{DateTime, DurationRound, Local, TimeDelta};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::net::IpAddr;
+
+/// Maximum number of names (or clients) to be counted to keep the memory bounded.
+/// When a map is full, the keys with the lowest counts are evicted to make room for new ones.
+const MAX_KEYS: usize = 100_000;
+/// Number of hours to keep the per-hour counts
+const MAX_HOURS: usize = 7 * 24;
+
+/// Number of queries in an hour
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HourlyCount {
+    pub queries: u64,
+    pub blocked: u64,
+}
+
+/// Query counts by name, client and hour since the start or the last reset
+#[derive(Debug, Default)]
+pub struct Stats {
+    queried: HashMap<String, u64>,
+    blocked: HashMap<String, u64>,
+    clients: HashMap<IpAddr, u64>,
+    hourly: BTreeMap<DateTime<Local>, HourlyCount>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, ctx: &QueryContext, status: &ResolvedStatus) {
+        self.record_at(ctx, status, Local::now());
+    }
+
+    fn record_at(&mut self, ctx: &QueryContext, status: &ResolvedStatus, now: DateTime<Local>) {
+        let blocked = matches!(status, ResolvedStatus::Deny(..));
+        increment(&mut self.queried, &ctx.qname, MAX_KEYS);
+        if blocked {
+            increment(&mut self.blocked, &ctx.qname, MAX_KEYS);
+        }
+        increment(&mut self.clients, &ctx.client.ip().to_canonical(), MAX_KEYS);
+
+        let hour = now.duration_trunc(TimeDelta::hours(1)).unwrap_or(now);
+        let count = self.hourly.entry(hour).or_default();
+        count.queries += 1;
+        if blocked {
+            count.blocked += 1;
+        }
+        while self.hourly.len() > MAX_HOURS {
+            self.hourly.pop_first();
+        }
+    }
+
+    /// Returns the most queried names in descending order of count
+    pub fn top_queried(&self, n: usize) -> Vec<(&str, u64)> {
+        top(&self.queried, n)
+            .into_iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect()
+    }
+
+    /// Returns the most denied names in descending order of count
+    pub fn top_blocked(&self, n: usize) -> Vec<(&str, u64)> {
+        top(&self.blocked, n)
+            .into_iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect()
+    }
+
+    /// Returns the clients that sent the most queries in descending order of count
+    pub fn top_clients(&self, n: usize) -> Vec<(IpAddr, u64)> {
+        top(&self.clients, n)
+            .into_iter()
+            .map(|(k, v)| (*k, v))
+            .collect()
+    }
+
+    /// Returns the counts of the last `n` hours, oldest first
+    pub fn hourly(&self, n: usize) -> Vec<(DateTime<Local>, HourlyCount)> {
+        let skip = self.hourly.len().saturating_sub(n);
+        self.hourly
+            .iter()
+            .skip(skip)
+            .map(|(k, v)| (*k, *v))
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Counts the key. If the map already has `max_keys` keys, a tenth of them with the lowest counts
+/// are evicted first, so that names that become popular later still reach the top of the reports.
+fn increment<K: Eq + Hash + Clone>(map: &mut HashMap<K, u64>, key: &K, max_keys: usize) {
+    if let Some(v) = map.get_mut(key) {
+        *v = v.saturating_add(1);
+        return;
+    }
+    if map.len() >= max_keys {
+        evict_lowest(map, (max_keys / 10).max(1));
+    }
+    map.insert(key.clone(), 1);
+}
+
+/// Removes `n` keys with the lowest counts
+fn evict_lowest<K: Eq + Hash>(map: &mut HashMap<K, u64>, n: usize) {
+    let mut counts = map.values().copied().collect::<Vec<_>>();
+    if counts.is_empty() {
+        return;
+    }
+    let n = n.min(counts.len());
+    let threshold = *counts.select_nth_unstable(n - 1).1;
+    // Keys with the same count as the threshold are removed only as many as needed
+    let mut ties = n - counts.iter().filter(|x| **x < threshold).count();
+    map.retain(|_, v| {
+        if *v < threshold {
+            false
+        } else if *v == threshold && ties > 0 {
+            ties -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
+
+fn top<K: Ord>(map: &HashMap<K, u64>, n: usize) -> Vec<(&K, u64)> {
+    let mut ret = map.iter().map(|(k, v)| (k, *v)).collect::<Vec<_>>();
+    ret.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    ret.truncate(n);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{QueryType, ResultCode};
+    use crate::resolved_data::ResolvedData;
+    use chrono::TimeZone;
+    use std::time::Instant;
+
+    #[test]
+    fn test_record() {
+        let mut stats = Stats::new();
+        let mut record = |client: &str, name: &str, deny: bool, hour: u32| {
+            let ctx = QueryContext::new(
+                0,
+                format!("{client}:50000").parse().unwrap(),
+                QueryType::A,
+                name,
+                Instant::now(),
+            );
+            let data = ResolvedData::new(QueryType::A, name);
+            let status = if deny {
+                ResolvedStatus::Deny(data, ResultCode::NXDomain, None)
+            } else {
+                ResolvedStatus::Allow(data, None)
+            };
+            let now = Local.with_ymd_and_hms(2024, 1, 1, hour, 30, 0).unwrap();
+            stats.record_at(&ctx, &status, now);
+        };
+        record("192.168.1.10", "www.example.com", false, 10);
+        record("192.168.1.10", "www.example.com", false, 10);
+        record("192.168.1.11", "ads.example.com", true, 10);
+        record("192.168.1.10", "ads.example.com", true, 11);
+        record("192.168.1.10", "tracker.example.com", true, 11);
+
+        assert_eq!(
+            vec![("ads.example.com", 2), ("www.example.com", 2)],
+            stats.top_queried(2)
+        );
+        assert_eq!(
+            vec![("ads.example.com", 2), ("tracker.example.com", 1)],
+            stats.top_blocked(10)
+        );
+        assert_eq!(
+            vec![
+                ("192.168.1.10".parse::<IpAddr>().unwrap(), 4),
+                ("192.168.1.11".parse::<IpAddr>().unwrap(), 1)
+            ],
+            stats.top_clients(10)
+        );
+
+        let hourly = stats.hourly(1);
+        assert_eq!(1, hourly.len());
+        assert_eq!(
+            Local.with_ymd_and_hms(2024, 1, 1, 11, 0, 0).unwrap(),
+            hourly[0].0
+        );
+        assert_eq!(
+            HourlyCount {
+                queries: 2,
+                blocked: 2
+            },
+            hourly[0].1
+        );
+        assert_eq!(2, stats.hourly(24).len());
+
+        stats.reset();
+        assert!(stats.top_queried(10).is_empty());
+    }
+
+    #[test]
+    fn test_increment_evicts_lowest() {
+        let mut map = HashMap::new();
+        for (key, count) in [("a", 5), ("b", 1), ("c", 3), ("d", 1)] {
+            for _ in 0..count {
+                increment(&mut map, &key, 4);
+            }
+        }
+        assert_eq!(4, map.len());
+
+        // One of the keys counted once makes room for the new key
+        increment(&mut map, &"e", 4);
+        assert_eq!(4, map.len());
+        assert_eq!(Some(&1), map.get("e"));
+        assert_eq!(Some(&5), map.get("a"));
+        assert_eq!(Some(&3), map.get("c"));
+        assert!(map.contains_key("b") != map.contains_key("d"));
+    }
+}
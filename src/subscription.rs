@@ -0,0 +1,259 @@
+use crate::filters::{CheckList, CompositeCheckList, ListFormat, ListKind};
+use crate::{Error, Result};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Timeout for downloading a list
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum size of a downloaded list
+const MAX_DOWNLOAD_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Interval before retrying a failed download
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A list downloaded from a URL and refreshed periodically
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub name: String,
+    pub url: String,
+    pub kind: ListKind,
+    pub format: ListFormat,
+    pub interval: Duration,
+    cache_path: PathBuf,
+}
+
+impl Subscription {
+    pub fn new(
+        name: impl Into<String>,
+        url: impl Into<String>,
+        kind: ListKind,
+        format: ListFormat,
+        interval: Duration,
+        cache_dir: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let name = name.into();
+        // The name is used as the file name of the cached copy
+        if name.is_empty() || name.contains('/') || name.contains("..") || name.contains('\0') {
+            return Err(Error::InvalidSubscriptionName(name));
+        }
+        let cache_path = cache_dir.as_ref().join(format!("{name}.txt"));
+        Ok(Self {
+            name,
+            url: url.into(),
+            kind,
+            format,
+            interval,
+            cache_path,
+        })
+    }
+
+    pub fn cache_path(&self) -> &Path {
+        &self.cache_path
+    }
+
+    /// Downloads the list, replaces the cached copy and returns the list.
+    /// The cached copy is left untouched if the download fails or the list has no entries,
+    /// such as an error page returned with a success status.
+    pub fn fetch(&self) -> Result<CheckList> {
+        let error = |message: &str| Error::Download(self.url.clone(), message.to_string());
+        let resp = ureq::get(&self.url)
+            .timeout(FETCH_TIMEOUT)
+            .call()
+            .map_err(|e| error(&e.to_string()))?;
+
+        let mut body = Vec::new();
+        resp.into_reader()
+            .take(MAX_DOWNLOAD_SIZE + 1)
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > MAX_DOWNLOAD_SIZE {
+            return Err(error(&format!(
+                "The list is larger than {} MiB",
+                MAX_DOWNLOAD_SIZE / 1024 / 1024
+            )));
+        }
+        let text = String::from_utf8(body).map_err(|_| error("The list is not UTF-8 text"))?;
+        let list = CheckList::import_text(self.cache_path.clone(), &text, self.format)?;
+        if list.count() == 0 {
+            return Err(error("The list has no entries"));
+        }
+
+        let tmp_path = self.cache_path.with_extension("tmp");
+        let ret = (|| -> Result<()> {
+            let mut f = File::create(&tmp_path)?;
+            f.write_all(text.as_bytes())?;
+            f.flush()?;
+            Ok(fs::rename(&tmp_path, &self.cache_path)?)
+        })();
+        if ret.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        ret.map(|_| list)
+    }
+
+    /// Loads the cached copy
+    pub fn load(&self) -> Result<CheckList> {
+        CheckList::import(self.cache_path.clone(), self.format)
+    }
+
+    /// Downloads the list and merges it into the checklist
+    pub fn refresh(&self, checklist: &RwLock<CompositeCheckList>) -> Result<usize> {
+        let list = self.fetch()?;
+        let count = list.count();
+        if let Ok(mut checklist) = checklist.write() {
+            checklist.set_remote(&self.name, self.kind, list);
+        }
+        Ok(count)
+    }
+
+    /// Returns the time until the next download based on the cached copy
+    fn due(&self) -> Duration {
+        fs::metadata(&self.cache_path)
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.elapsed().ok())
+            .map_or(Duration::ZERO, |x| self.interval.saturating_sub(x))
+    }
+}
+
+/// Spawns a thread that refreshes the subscriptions periodically
+pub fn spawn_updater(
+    subscriptions: Vec<Subscription>,
+    checklist: Arc<RwLock<CompositeCheckList>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let now = Instant::now();
+        let mut schedule = subscriptions
+            .iter()
+            .map(|x| now + x.due())
+            .collect::<Vec<_>>();
+        loop {
+            for (subscription, next) in subscriptions.iter().zip(schedule.iter_mut()) {
+                if *next > Instant::now() {
+                    continue;
+                }
+
+                *next = match subscription.refresh(&checklist) {
+                    Ok(count) => {
+                        tracing::info!(
+                            "Refreshed subscription {} ({count} FQDN(s))",
+                            subscription.name
+                        );
+                        Instant::now() + subscription.interval
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to refresh subscription {}, keeping the last good copy ({e})",
+                            subscription.name
+                        );
+                        Instant::now() + subscription.interval.min(RETRY_INTERVAL)
+                    }
+                };
+            }
+
+            if let Some(next) = schedule.iter().min() {
+                thread::sleep(next.saturating_duration_since(Instant::now()));
+            } else {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::CheckStatus;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    /// Starts an HTTP server that returns the responses in order
+    fn serve(responses: Vec<(&'static str, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+        format!("http://{addr}/hosts")
+    }
+
+    #[test]
+    fn test_refresh() {
+        let url = serve(vec![
+            ("200 OK", "0.0.0.0 ads.example.com\n"),
+            ("500 Internal Server Error", ""),
+            ("200 OK", "<html><body>Login required</body></html>\n"),
+        ]);
+        let cache_dir = std::env::temp_dir().join(format!("ldf-test-sub-{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let subscription = Subscription::new(
+            "test",
+            url,
+            ListKind::Deny,
+            ListFormat::Auto,
+            Duration::from_secs(3600),
+            &cache_dir,
+        )
+        .unwrap();
+        assert_eq!(Duration::ZERO, subscription.due());
+
+        let mut list = CompositeCheckList::default();
+        list.allowlist.add("ads.example.com");
+        let checklist = RwLock::new(list);
+        assert_eq!(1, subscription.refresh(&checklist).unwrap());
+        assert_eq!(
+            CheckStatus::Deny,
+            checklist.read().unwrap().check("ads.example.com")
+        );
+        assert!(subscription.due() > Duration::ZERO);
+        // The list is reloaded from the cached copy
+        assert_eq!(
+            Some(subscription.cache_path()),
+            checklist.read().unwrap().remote_denylists["test"].path()
+        );
+
+        // The last good copy is kept
+        assert!(subscription.refresh(&checklist).is_err());
+        assert_eq!(
+            CheckStatus::Deny,
+            checklist.read().unwrap().check("ads.example.com")
+        );
+        assert_eq!(1, subscription.load().unwrap().count());
+
+        // A page without entries does not replace the cached copy
+        assert!(subscription.refresh(&checklist).is_err());
+        assert_eq!(1, subscription.load().unwrap().count());
+        assert!(!cache_dir.join("test.tmp").exists());
+
+        for name in ["", "../test", "a/b"] {
+            assert!(Subscription::new(
+                name,
+                "http://127.0.0.1/",
+                ListKind::Deny,
+                ListFormat::Auto,
+                Duration::from_secs(3600),
+                &cache_dir
+            )
+            .is_err());
+        }
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use crate::error::{Error, Result};
+use crate::logger::{AUDIT_TARGET, QUERY_TARGET};
+use std::fmt::Write;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Facility of the messages (daemon)
+const FACILITY: u8 = 3;
+/// SD-ID of the structured data, using the enterprise number reserved for documentation
+const SD_ID: &str = "ldf@32473";
+
+/// Destination of the syslog messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    Unix(PathBuf),
+    Udp(SocketAddr),
+}
+
+impl Default for SyslogTarget {
+    fn default() -> Self {
+        Self::Unix(PathBuf::from("/dev/log"))
+    }
+}
+
+impl FromStr for SyslogTarget {
+    type Err = Error;
+
+    /// Parses "unix:/dev/log" or "udp:192.0.2.1:514"
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Self::Unix(PathBuf::from(path)))
+        } else if let Some(addr) = s.strip_prefix("udp:") {
+            addr.parse()
+                .map(Self::Udp)
+                .map_err(|_| Error::InvalidSyslogTarget(s.to_string()))
+        } else {
+            Err(Error::InvalidSyslogTarget(s.to_string()))
+        }
+    }
+}
+
+impl std::fmt::Display for SyslogTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Udp(addr) => write!(f, "udp:{addr}"),
+        }
+    }
+}
+
+enum Socket {
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket, SocketAddr),
+}
+
+/// Layer that sends events as RFC 5424 syslog messages.
+/// Fields other than the message are sent as structured data.
+pub struct SyslogLayer {
+    socket: Socket,
+    hostname: String,
+    app_name: String,
+    procid: u32,
+}
+
+impl SyslogLayer {
+    pub fn new(target: &SyslogTarget, app_name: impl Into<String>) -> Result<Self> {
+        let socket = match target {
+            SyslogTarget::Unix(path) => Socket::Unix(UnixDatagram::unbound()?, path.clone()),
+            SyslogTarget::Udp(addr) => {
+                let local: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                Socket::Udp(UdpSocket::bind(local)?, *addr)
+            }
+        };
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|x| x.trim().to_string())
+            .ok()
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| "-".into());
+        Ok(Self {
+            socket,
+            hostname,
+            app_name: app_name.into(),
+            procid: std::process::id(),
+        })
+    }
+
+    fn format(&self, level: &Level, target: &str, fields: &Fields) -> String {
+        let severity = match *level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let msgid = match target {
+            QUERY_TARGET => "query",
+            AUDIT_TARGET => "audit",
+            _ => "-",
+        };
+        let mut ret = format!(
+            "<{}>1 {timestamp} {} {} {} {msgid} ",
+            FACILITY * 8 + severity,
+            self.hostname,
+            self.app_name,
+            self.procid
+        );
+        if fields.values.is_empty() {
+            ret.push('-');
+        } else {
+            let _ = write!(ret, "[{SD_ID}");
+            for (name, value) in fields.values.iter() {
+                let _ = write!(ret, " {name}=\"{}\"", escape_param(value));
+            }
+            ret.push(']');
+        }
+        if !fields.message.is_empty() {
+            ret.push(' ');
+            ret.push_str(&fields.message);
+        }
+        ret
+    }
+
+    fn send(&self, message: &str) {
+        // Errors are ignored since they cannot be logged
+        let _ = match &self.socket {
+            Socket::Unix(socket, path) => socket.send_to(message.as_bytes(), path),
+            Socket::Udp(socket, addr) => socket.send_to(message.as_bytes(), addr),
+        };
+    }
+}
+
+/// Escapes the characters that cannot be written as is in PARAM-VALUE
+fn escape_param(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    values: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.values.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.values.push((field.name(), format!("{value:?}")));
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SyslogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        self.send(&self.format(meta.level(), meta.target(), &fields));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            SyslogTarget::Unix(PathBuf::from("/dev/log")),
+            "unix:/dev/log".parse().unwrap()
+        );
+        let target = "udp:127.0.0.1:514".parse::<SyslogTarget>().unwrap();
+        assert_eq!(SyslogTarget::Udp(([127, 0, 0, 1], 514).into()), target);
+        assert!("tcp:127.0.0.1:514".parse::<SyslogTarget>().is_err());
+
+        let layer = SyslogLayer::new(&target, "ldf").unwrap();
+        let fields = Fields {
+            message: "[Deny] <A> ads.example.com".into(),
+            values: vec![
+                ("qname", "ads.example.com".into()),
+                ("rule", "a\"b]".into()),
+            ],
+        };
+        let ret = layer.format(&Level::INFO, QUERY_TARGET, &fields);
+        assert!(ret.starts_with("<30>1 "), "{ret}");
+        assert!(
+            ret.ends_with(&format!(
+                " ldf {} query [ldf@32473 qname=\"ads.example.com\" rule=\"a\\\"b\\]\"] [Deny] <A> ads.example.com",
+                std::process::id()
+            )),
+            "{ret}"
+        );
+
+        let ret = layer.format(&Level::ERROR, "ldf", &Fields::default());
+        assert!(ret.starts_with("<27>1 "), "{ret}");
+        assert!(ret.ends_with(" - -"), "{ret}");
+
+        let ret = layer.format(&Level::INFO, AUDIT_TARGET, &Fields::default());
+        assert!(ret.ends_with(" audit -"), "{ret}");
+    }
+}